@@ -0,0 +1,331 @@
+//! Append-only audit log of lock sessions, `audit.jsonl` next to `auth.json`.
+//!
+//! Each line is one JSON entry carrying the SHA-256 of the entry before it,
+//! so editing or removing a line breaks the chain for every line after it.
+//! The last entry's number and hash are also kept in `audit.head`, so cutting
+//! entries off the end breaks the chain too.
+
+use crate::auth::Credential;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// What the first entry chains to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    LockStarted,
+    /// A rejected unlock attempt. What was typed is never recorded.
+    UnlockFailed,
+    Unlocked {
+        method: Credential,
+        duration_secs: u64,
+    },
+}
+
+/// The part of an entry the hash covers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Body {
+    seq: u64,
+    /// Unix timestamp (seconds).
+    time: u64,
+    pid: u32,
+    #[serde(flatten)]
+    event: AuditEvent,
+    prev: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    body: Body,
+    hash: String,
+}
+
+impl AuditEntry {
+    pub fn time(&self) -> u64 {
+        self.body.time
+    }
+
+    /// The process that wrote the entry; the entries of one lock share it.
+    pub fn pid(&self) -> u32 {
+        self.body.pid
+    }
+
+    pub fn event(&self) -> &AuditEvent {
+        &self.body.event
+    }
+}
+
+/// The last entry in the log, kept outside it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Head {
+    seq: u64,
+    hash: String,
+}
+
+impl Head {
+    fn path(log: &Path) -> PathBuf {
+        log.with_extension("head")
+    }
+
+    fn load(log: &Path) -> Result<Option<Self>> {
+        let path = Self::path(log);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path).context("Failed to read audit head")?;
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    /// Replace the head in one step, so a crash leaves the old or new one.
+    fn save(&self, log: &Path) -> Result<()> {
+        let path = Self::path(log);
+        let tmp = path.with_extension("head.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?).context("Failed to write audit head")?;
+        fs::rename(&tmp, &path).context("Failed to write audit head")?;
+        Ok(())
+    }
+}
+
+/// Hold `audit.lock` exclusively until the returned file is dropped, so two
+/// locks appending at once cannot both chain to the same entry.
+fn lock_log(log: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(log.with_extension("lock"))
+        .context("Failed to open audit lock")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to lock audit log");
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::Foundation::HANDLE;
+        use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+        use windows::Win32::System::IO::OVERLAPPED;
+        let mut overlapped = OVERLAPPED::default();
+        unsafe {
+            LockFileEx(
+                HANDLE(file.as_raw_handle() as isize),
+                LOCKFILE_EXCLUSIVE_LOCK,
+                0,
+                1,
+                0,
+                &mut overlapped,
+            )
+        }
+        .context("Failed to lock audit log")?;
+    }
+    Ok(file)
+}
+
+impl Body {
+    fn hash(&self) -> Result<String> {
+        let json = serde_json::to_string(self)?;
+        Ok(hex::encode(Sha256::digest(json.as_bytes())))
+    }
+}
+
+pub fn get_audit_path() -> Result<PathBuf> {
+    Ok(crate::auth::get_config_path()?.with_file_name("audit.jsonl"))
+}
+
+/// Append `event` to the log at the default path. Auditing must never keep
+/// the screen locked, so failures are only reported.
+pub fn record(event: AuditEvent) {
+    let result = get_audit_path().and_then(|path| {
+        append(&path, event, crate::auth::unix_now())?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: failed to write audit log: {:#}", e);
+    }
+}
+
+/// Append `event` at `now`, chained to the last entry in the log at `path`.
+pub fn append(path: &Path, event: AuditEvent, now: u64) -> Result<AuditEntry> {
+    let _lock = lock_log(path)?;
+    let last = read(path)?.pop();
+    let body = Body {
+        seq: last.as_ref().map_or(0, |entry| entry.body.seq + 1),
+        time: now,
+        pid: std::process::id(),
+        event,
+        prev: last.map_or_else(|| GENESIS.to_string(), |entry| entry.hash),
+    };
+    let entry = AuditEntry {
+        hash: body.hash()?,
+        body,
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Head {
+        seq: entry.body.seq,
+        hash: entry.hash.clone(),
+    }
+    .save(path)?;
+    Ok(entry)
+}
+
+/// Every entry in the log at `path`, oldest first. A missing log is empty.
+pub fn read(path: &Path) -> Result<Vec<AuditEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path).context("Failed to read audit log")?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).with_context(|| {
+                format!("Line {} of the audit log is not a valid entry", index + 1)
+            })
+        })
+        .collect()
+}
+
+/// Check that every entry in the log at `path` is intact and chained to the
+/// one before it, and that the last is the one `audit.head` names, returning
+/// how many there are.
+pub fn verify(path: &Path) -> Result<usize> {
+    let _lock = lock_log(path)?;
+    let entries = read(path)?;
+    let mut prev = GENESIS.to_string();
+    for (index, entry) in entries.iter().enumerate() {
+        let line = index + 1;
+        if entry.body.seq != index as u64 {
+            bail!(
+                "Line {}: expected entry {}, found {} (entries removed or reordered)",
+                line,
+                index,
+                entry.body.seq
+            );
+        }
+        if entry.body.prev != prev {
+            bail!("Line {}: does not follow the entry before it", line);
+        }
+        if entry.body.hash()? != entry.hash {
+            bail!(
+                "Line {}: contents do not match its hash (entry altered)",
+                line
+            );
+        }
+        prev = entry.hash.clone();
+    }
+
+    let head = Head::load(path)?;
+    match (entries.last(), head) {
+        (None, None) => {}
+        (Some(_), None) => bail!("No audit head next to the log (head removed)"),
+        (None, Some(head)) => bail!(
+            "Log is empty but entry {} was written (entries removed)",
+            head.seq
+        ),
+        (Some(last), Some(head)) => {
+            if last.body.seq != head.seq || last.hash != head.hash {
+                bail!(
+                    "Log ends at entry {} but entry {} was written last (entries removed)",
+                    last.body.seq,
+                    head.seq
+                );
+            }
+        }
+    }
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        fs::remove_file(&path).ok();
+        fs::remove_file(Head::path(&path)).ok();
+
+        assert_eq!(verify(&path).unwrap(), 0);
+        append(&path, AuditEvent::LockStarted, 100).unwrap();
+        append(&path, AuditEvent::UnlockFailed, 110).unwrap();
+        let unlocked = AuditEvent::Unlocked {
+            method: Credential::Primary,
+            duration_secs: 20,
+        };
+        append(&path, unlocked.clone(), 120).unwrap();
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains(r#""event":"unlocked","method":"primary","duration_secs":20"#));
+        let entries = read(&path).unwrap();
+        assert_eq!(entries[2].body.event, unlocked);
+        assert_eq!(entries[1].body.time, 110);
+
+        // Rewriting the time of the failed attempt breaks its hash.
+        fs::write(&path, text.replace(r#""time":110"#, r#""time":111"#)).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.starts_with("Line 2: contents"), "{}", error);
+
+        // So does dropping it altogether.
+        let lines: Vec<&str> = text.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.starts_with("Line 2: expected entry 1"), "{}", error);
+
+        // Cutting the last entry off is caught by the head.
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(
+            error.starts_with("Log ends at entry 1 but entry 2"),
+            "{}",
+            error
+        );
+        fs::write(&path, "").unwrap();
+        assert!(verify(&path).is_err());
+        fs::remove_file(Head::path(&path)).unwrap();
+        fs::write(&path, &text).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.starts_with("No audit head"), "{}", error);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_concurrent_appends_stay_chained() {
+        let dir =
+            std::env::temp_dir().join(format!("agent-lock-audit-race-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for time in 0..10 {
+                        append(&path, AuditEvent::UnlockFailed, time).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(verify(&path).unwrap(), 40);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
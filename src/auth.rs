@@ -1,34 +1,555 @@
+use crate::duration::format_duration;
+use crate::totp;
 use anyhow::{Context, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Argon2id cost parameters used when hashing a new PIN.
+///
+/// Existing hashes are always verified with the parameters embedded in them;
+/// when these settings change, the stored hash is re-computed on the next
+/// successful unlock.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// How `pin_hash` is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    /// Unsalted hex SHA-256, written by versions before Argon2id support.
+    LegacySha256,
+    /// PHC string produced by Argon2id.
+    Argon2id,
+}
+
+impl HashScheme {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashScheme::LegacySha256 => "sha256-legacy",
+            HashScheme::Argon2id => "argon2id",
+        }
+    }
+}
+
+/// How failed unlock attempts are throttled.
+///
+/// The first `free_attempts` failures are free. Each further failure doubles
+/// the wait, starting at `base_delay_secs` and capped at `max_delay_secs`.
+/// Once `hard_lockout_attempts` is reached, every failure imposes the full
+/// `hard_cooldown_secs` until a correct PIN is entered.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LockoutPolicy {
+    pub free_attempts: u32,
+    pub base_delay_secs: u64,
+    pub max_delay_secs: u64,
+    pub hard_lockout_attempts: u32,
+    pub hard_cooldown_secs: u64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            base_delay_secs: 5,
+            max_delay_secs: 300,
+            hard_lockout_attempts: 10,
+            hard_cooldown_secs: 900,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    fn delay_after(&self, failed_attempts: u32) -> u64 {
+        if failed_attempts >= self.hard_lockout_attempts {
+            return self.hard_cooldown_secs;
+        }
+        if failed_attempts <= self.free_attempts {
+            return 0;
+        }
+        let exponent = (failed_attempts - self.free_attempts - 1).min(32);
+        self.base_delay_secs
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_delay_secs)
+    }
+}
+
+/// Failed-attempt counter, persisted next to `auth.json` so restarting the
+/// lock does not reset it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockoutState {
+    pub failed_attempts: u32,
+    /// Unix timestamp (seconds) before which attempts are refused.
+    pub locked_until: Option<u64>,
+    /// Unix timestamp (seconds) of the last successful unlock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_unlock: Option<u64>,
+}
+
+impl LockoutState {
+    pub fn remaining(&self, now: u64) -> Option<Duration> {
+        self.locked_until
+            .filter(|&until| until > now)
+            .map(|until| Duration::from_secs(until - now))
+    }
+
+    pub fn record_failure(&mut self, policy: &LockoutPolicy, now: u64) {
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        let delay = policy.delay_after(self.failed_attempts);
+        self.locked_until = (delay > 0).then(|| now + delay);
+    }
+
+    pub fn clear_failures(&mut self) {
+        self.failed_attempts = 0;
+        self.locked_until = None;
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).context("Failed to save lockout state")?;
+        Ok(())
+    }
+
+    /// Load the state at `path`, treating a missing file as a clean slate.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path).context("Failed to read lockout state")?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Whether the secret is a numeric PIN or a free-form passphrase.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialKind {
+    #[default]
+    Pin,
+    Passphrase,
+}
+
+impl CredentialKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            CredentialKind::Pin => "PIN",
+            CredentialKind::Passphrase => "passphrase",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    fn matches(self, c: char) -> bool {
+        match self {
+            CharClass::Lower => c.is_ascii_lowercase(),
+            CharClass::Upper => c.is_ascii_uppercase(),
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Symbol => c.is_ascii_punctuation(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CharClass::Lower => "a lowercase letter",
+            CharClass::Upper => "an uppercase letter",
+            CharClass::Digit => "a digit",
+            CharClass::Symbol => "a symbol",
+        }
+    }
+}
+
+/// What a new PIN or passphrase has to look like. Checked when it is set,
+/// not when unlocking, so tightening the policy never locks anyone out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct CredentialPolicy {
+    pub kind: CredentialKind,
+    /// Length limits, in characters.
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Character classes a passphrase must contain at least one of each.
+    pub required_classes: Vec<CharClass>,
+}
+
+impl Default for CredentialPolicy {
+    fn default() -> Self {
+        Self {
+            kind: CredentialKind::Pin,
+            min_length: 4,
+            max_length: 8,
+            required_classes: Vec::new(),
+        }
+    }
+}
+
+impl CredentialPolicy {
+    pub fn passphrase() -> Self {
+        Self {
+            kind: CredentialKind::Passphrase,
+            min_length: 12,
+            max_length: 100,
+            required_classes: Vec::new(),
+        }
+    }
+
+    /// The rules in words, e.g. "4-8 digits".
+    pub fn describe(&self) -> String {
+        let mut text = match self.kind {
+            CredentialKind::Pin => format!("{}-{} digits", self.min_length, self.max_length),
+            CredentialKind::Passphrase => {
+                format!("{}-{} characters", self.min_length, self.max_length)
+            }
+        };
+        if self.kind == CredentialKind::Passphrase && !self.required_classes.is_empty() {
+            let classes: Vec<_> = self.required_classes.iter().map(|c| c.name()).collect();
+            text.push_str(&format!(" with {}", classes.join(", ")));
+        }
+        text
+    }
+
+    pub fn check(&self, secret: &str) -> Result<()> {
+        let name = self.kind.name();
+        let length = secret.chars().count();
+        if length < self.min_length || length > self.max_length {
+            anyhow::bail!("The {} must be {}", name, self.describe());
+        }
+        match self.kind {
+            CredentialKind::Pin => {
+                if !secret.chars().all(|c| c.is_ascii_digit()) {
+                    anyhow::bail!("The PIN must be {}", self.describe());
+                }
+            }
+            CredentialKind::Passphrase => {
+                // The X11 and Wayland overlays only translate printable ASCII
+                // keys, so anything else could never be typed to unlock.
+                if !secret.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+                    anyhow::bail!(
+                        "The passphrase can only contain letters a-z and A-Z, digits, spaces and ASCII symbols"
+                    );
+                }
+                if let Some(class) = self
+                    .required_classes
+                    .iter()
+                    .find(|class| !secret.chars().any(|c| class.matches(c)))
+                {
+                    anyhow::bail!("The passphrase must contain {}", class.name());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the lock screen asks for once TOTP is enrolled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TotpMode {
+    /// The PIN followed by the current authenticator code.
+    #[value(name = "pin+code")]
+    #[serde(rename = "pin+code")]
+    PinAndCode,
+    /// The authenticator code alone.
+    #[value(name = "code")]
+    #[serde(rename = "code")]
+    CodeOnly,
+}
+
+/// Time-based one-time password settings (RFC 6238).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TotpConfig {
+    /// Base32 shared secret, as shown to the authenticator app.
+    pub secret: String,
+    pub mode: TotpMode,
+    /// How many 30 second steps before or after now a code is accepted for,
+    /// to allow for clock drift between this machine and the phone.
+    #[serde(default = "default_totp_skew")]
+    pub skew_steps: u64,
+    /// Step of the last accepted code, so a code cannot be used twice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_step: Option<u64>,
+}
+
+fn default_totp_skew() -> u64 {
+    1
+}
+
+/// A second PIN that unlocks like the real one but quietly sets off the
+/// configured actions, for when someone is forced to unlock.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DuressConfig {
+    /// Argon2id hash of the duress PIN or passphrase.
+    pub pin_hash: String,
+    /// Command to start, as program and arguments (not run through a shell).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hook: Vec<String>,
+    /// File to write the time of the duress unlock to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marker_file: Option<PathBuf>,
+    /// Processes to stop, each a PID or a process name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_processes: Vec<String>,
+}
+
+/// Which credential an unlock attempt matched.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Credential {
+    /// The PIN or passphrase, with the authenticator code if one is set up.
+    Primary,
+    RecoveryCode,
+    Duress,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AuthConfig {
+    /// Hash of the PIN or passphrase, whichever `credential` says.
     pub pin_hash: String,
+    #[serde(default)]
+    pub credential: CredentialPolicy,
+    #[serde(default)]
+    pub kdf: KdfParams,
+    #[serde(default)]
+    pub lockout: LockoutPolicy,
+    /// Argon2id hashes of the unused recovery codes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<TotpConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duress: Option<DuressConfig>,
 }
 
 impl AuthConfig {
-    pub fn new(pin: &str) -> Self {
-        Self {
-            pin_hash: Self::hash_pin(pin),
+    pub fn new(pin: &str) -> Result<Self> {
+        Self::with_params(pin, KdfParams::default())
+    }
+
+    pub fn with_params(pin: &str, kdf: KdfParams) -> Result<Self> {
+        Ok(Self {
+            pin_hash: Self::hash_pin(pin, &kdf)?,
+            credential: CredentialPolicy::default(),
+            kdf,
+            lockout: LockoutPolicy::default(),
+            recovery_codes: Vec::new(),
+            totp: None,
+            duress: None,
+        })
+    }
+
+    /// Replace the stored hash with one of `pin`, keeping the other settings.
+    pub fn set_pin(&mut self, pin: &str) -> Result<()> {
+        if self.verify_duress(pin) {
+            anyhow::bail!(
+                "The {} must differ from the duress one",
+                self.credential.kind.name()
+            );
         }
+        self.pin_hash = Self::hash_pin(pin, &self.kdf)?;
+        Ok(())
+    }
+
+    fn hash_pin(pin: &str, kdf: &KdfParams) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = kdf
+            .argon2()?
+            .hash_password(pin.as_bytes(), &salt)
+            .map_err(|e| anyhow::anyhow!("Failed to hash PIN: {}", e))?;
+        Ok(hash.to_string())
     }
 
-    fn hash_pin(pin: &str) -> String {
+    fn legacy_hash_pin(pin: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(pin.as_bytes());
         hex::encode(hasher.finalize())
     }
 
-    pub fn verify(&self, pin: &str) -> bool {
-        self.pin_hash == Self::hash_pin(pin)
+    pub fn scheme(&self) -> HashScheme {
+        if self.pin_hash.starts_with("$argon2") {
+            HashScheme::Argon2id
+        } else {
+            HashScheme::LegacySha256
+        }
+    }
+
+    /// The Argon2 version recorded in the hash (19 for Argon2 1.3), or `None`
+    /// for legacy hashes.
+    pub fn hash_version(&self) -> Option<u32> {
+        PasswordHash::new(&self.pin_hash).ok()?.version
+    }
+
+    /// Whether the stored hash is legacy or was made with other cost parameters.
+    pub fn needs_rehash(&self) -> bool {
+        let Ok(parsed) = PasswordHash::new(&self.pin_hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || params.m_cost() != self.kdf.memory_kib
+            || params.t_cost() != self.kdf.iterations
+            || params.p_cost() != self.kdf.parallelism
+    }
+
+    /// Check `pin` against the stored hash.
+    ///
+    /// On success, a legacy or outdated hash is replaced in place with a fresh
+    /// Argon2id hash; callers persist the config if `pin_hash` changed.
+    pub fn verify(&mut self, pin: &str) -> bool {
+        let matched = match self.scheme() {
+            HashScheme::LegacySha256 => constant_time_eq(
+                self.pin_hash.as_bytes(),
+                Self::legacy_hash_pin(pin).as_bytes(),
+            ),
+            HashScheme::Argon2id => argon2_matches(&self.pin_hash, pin),
+        };
+
+        if matched && self.needs_rehash() {
+            match Self::hash_pin(pin, &self.kdf) {
+                Ok(hash) => self.pin_hash = hash,
+                Err(e) => eprintln!("Failed to upgrade PIN hash: {}", e),
+            }
+        }
+
+        matched
+    }
+
+    /// Check an authenticator `code` at `now` (unix seconds), within the
+    /// configured skew window. A code is only accepted once; callers persist
+    /// the config on success.
+    pub fn verify_totp(&mut self, code: &str, now: u64) -> bool {
+        let Some(totp) = self.totp.as_mut() else {
+            return false;
+        };
+        let Ok(secret) = totp::decode_secret(&totp.secret) else {
+            return false;
+        };
+        match totp::matching_step(&secret, code, now, totp.skew_steps) {
+            Some(step) if totp.last_step.is_none_or(|last| step > last) => {
+                totp.last_step = Some(step);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check `pin` against the duress PIN, if one is set.
+    pub fn verify_duress(&self, pin: &str) -> bool {
+        self.duress
+            .as_ref()
+            .is_some_and(|duress| argon2_matches(&duress.pin_hash, pin))
+    }
+
+    /// Check what was typed on the lock screen: the PIN, the PIN followed
+    /// by an authenticator code, or the code alone, depending on `totp`.
+    /// The duress PIN is accepted wherever the PIN is, and on its own when
+    /// only a code is asked for.
+    pub fn verify_unlock(&mut self, input: &str, now: u64) -> Option<Credential> {
+        match self.totp.as_ref().map(|totp| totp.mode) {
+            None => self.check_pin(input),
+            Some(TotpMode::PinAndCode) => {
+                let (pin, code) = split_code(input)?;
+                let credential = self.check_pin(pin)?;
+                self.verify_totp(code, now).then_some(credential)
+            }
+            Some(TotpMode::CodeOnly) => {
+                if self.verify_totp(input, now) {
+                    Some(Credential::Primary)
+                } else {
+                    self.verify_duress(input).then_some(Credential::Duress)
+                }
+            }
+        }
+    }
+
+    fn check_pin(&mut self, pin: &str) -> Option<Credential> {
+        if self.verify(pin) {
+            Some(Credential::Primary)
+        } else if self.verify_duress(pin) {
+            Some(Credential::Duress)
+        } else {
+            None
+        }
+    }
+
+    /// Replace any existing recovery codes with a fresh set, returning the
+    /// codes in the form they are shown to the user.
+    pub fn generate_recovery_codes(&mut self) -> Result<Vec<String>> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| new_recovery_code())
+            .collect();
+        self.recovery_codes = codes
+            .iter()
+            .map(|code| Self::hash_pin(&normalize_recovery_code(code), &self.kdf))
+            .collect::<Result<_>>()?;
+        Ok(codes)
+    }
+
+    /// Check `input` against the unused recovery codes, removing the code it
+    /// matches so it cannot be used again. Callers persist the config.
+    pub fn use_recovery_code(&mut self, input: &str) -> bool {
+        let code = normalize_recovery_code(input);
+        if code.len() != RECOVERY_CODE_LEN || !code.bytes().all(|b| RECOVERY_ALPHABET.contains(&b))
+        {
+            return false;
+        }
+        let matched = self
+            .recovery_codes
+            .iter()
+            .position(|hash| argon2_matches(hash, &code));
+        match matched {
+            Some(index) => {
+                self.recovery_codes.remove(index);
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn save(&self, path: &PathBuf) -> Result<()> {
         let json = serde_json::to_string_pretty(self)?;
         fs::write(path, json).context("Failed to save auth config")?;
+        // The TOTP secret is stored as is, so only the owner may read it.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
         Ok(())
     }
 
@@ -39,6 +560,56 @@ impl AuthConfig {
     }
 }
 
+/// Crockford base32: no I, L, O or U, so codes survive being read aloud or
+/// written down.
+const RECOVERY_ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+const RECOVERY_CODE_LEN: usize = 10;
+const RECOVERY_CODE_COUNT: usize = 8;
+
+/// A random recovery code such as `7gk2m-x9qpe` (50 bits).
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: String = bytes
+        .iter()
+        .map(|b| char::from(RECOVERY_ALPHABET[usize::from(b % 32)]))
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Lowercase `input` and drop dashes and spaces, so codes can be typed in
+/// any case and grouping.
+fn normalize_recovery_code(input: &str) -> String {
+    input
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Split `input` into the PIN and the trailing authenticator code, allowing
+/// one space between them.
+fn split_code(input: &str) -> Option<(&str, &str)> {
+    let at = input.len().checked_sub(totp::DIGITS).filter(|&at| at > 0)?;
+    if !input.is_char_boundary(at) {
+        return None;
+    }
+    let (pin, code) = input.split_at(at);
+    Some((pin.strip_suffix(' ').unwrap_or(pin), code))
+}
+
+fn argon2_matches(hash: &str, secret: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(secret.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 pub fn get_config_path() -> Result<PathBuf> {
     let config_dir = dirs::config_dir()
         .context("Failed to get config directory")?
@@ -48,65 +619,770 @@ pub fn get_config_path() -> Result<PathBuf> {
     Ok(config_dir.join("auth.json"))
 }
 
-pub fn setup_pin() -> Result<()> {
-    println!("Set up your PIN (4-8 digits):");
-    let pin = rpassword::read_password()?;
+pub fn get_lockout_path() -> Result<PathBuf> {
+    Ok(get_config_path()?.with_file_name("lockout.json"))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Time left before another unlock attempt is accepted, if any.
+pub fn lockout_remaining() -> Option<Duration> {
+    let path = get_lockout_path().ok()?;
+    LockoutState::load(&path).ok()?.remaining(unix_now())
+}
+
+/// How recently `reset-confirm` must have been created for `reset` to
+/// accept it, so a forgotten file does not leave a standing way in.
+const RESET_CONFIRM_MAX_AGE: Duration = Duration::from_secs(10 * 60);
 
-    if pin.len() < 4 || pin.len() > 8 || !pin.chars().all(|c| c.is_numeric()) {
-        anyhow::bail!("PIN must be 4-8 digits");
+/// Configure a PIN or passphrase following `policy`, and with `totp` also
+/// enroll an authenticator app.
+pub fn setup_pin(policy: CredentialPolicy, totp: Option<TotpMode>) -> Result<()> {
+    let path = get_config_path()?;
+    if path.exists() {
+        anyhow::bail!(
+            "A PIN is already configured. Use 'agent-lock change-pin' to change it, \
+             or 'agent-lock reset' if it is forgotten"
+        );
     }
 
-    println!("Confirm your PIN:");
-    let confirm = rpassword::read_password()?;
+    let secret = read_new_secret(&policy, "Set up your")?;
+    let mut config = AuthConfig::new(&secret)?;
+    let name = policy.kind.name();
+    config.credential = policy;
+    let codes = config.generate_recovery_codes()?;
+    if let Some(mode) = totp {
+        config.totp = Some(enroll_totp(mode)?);
+    }
+    config.save(&path)?;
+
+    println!("{} configured successfully!", capitalized(name));
+    print_recovery_codes(&codes);
+    Ok(())
+}
 
-    if pin != confirm {
-        anyhow::bail!("PINs do not match");
+/// Enroll a new authenticator with `mode`, or turn TOTP off with `None`,
+/// after checking the current PIN.
+pub fn configure_totp(mode: Option<TotpMode>) -> Result<()> {
+    let path = get_config_path()?;
+    if !path.exists() {
+        anyhow::bail!("No PIN configured. Run 'agent-lock setup' first.");
+    }
+
+    let mut config = confirm_current(&path)?;
+    config.totp = match mode {
+        Some(mode) => Some(enroll_totp(mode)?),
+        None => None,
+    };
+    config.save(&path)?;
+    match mode {
+        Some(_) => println!("Authenticator enrolled. It is needed from the next lock on."),
+        None => println!("Authenticator codes are no longer needed to unlock."),
+    }
+    Ok(())
+}
+
+/// Show a new secret as an `otpauth://` URI and QR code, and only return
+/// it once the app has produced a matching code.
+fn enroll_totp(mode: TotpMode) -> Result<TotpConfig> {
+    let secret = totp::generate_secret();
+    let account = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    let uri = totp::uri(&secret, &account);
+
+    println!("\nScan this with your authenticator app:\n");
+    println!("{}", totp::qr_code(&uri)?);
+    println!("or add it by hand: {}\n", uri);
+    println!("Enter the 6-digit code the app shows:");
+    let code = rpassword::read_password()?;
+
+    let mut config = TotpConfig {
+        secret,
+        mode,
+        skew_steps: default_totp_skew(),
+        last_step: None,
+    };
+    let bytes = totp::decode_secret(&config.secret)?;
+    match totp::matching_step(&bytes, code.trim(), unix_now(), config.skew_steps) {
+        Some(step) => config.last_step = Some(step),
+        None => anyhow::bail!("Code did not match; the authenticator was not enrolled"),
+    }
+    Ok(config)
+}
+
+/// Set a duress PIN, after checking the current PIN, that unlocks as usual
+/// but also runs `hook`, writes `marker_file` and stops `stop_processes`.
+pub fn configure_duress(
+    hook: Vec<String>,
+    marker_file: Option<PathBuf>,
+    stop_processes: Vec<String>,
+) -> Result<()> {
+    let path = get_config_path()?;
+    if !path.exists() {
+        anyhow::bail!("No PIN configured. Run 'agent-lock setup' first.");
+    }
+    if hook.is_empty() && marker_file.is_none() && stop_processes.is_empty() {
+        anyhow::bail!("Give at least one of --hook, --marker or --stop");
+    }
+
+    let mut config = confirm_current(&path)?;
+    let policy = config.credential.clone();
+    let secret = read_new_secret(&policy, "Duress")?;
+    if config.verify(&secret) {
+        anyhow::bail!(
+            "The duress {} must differ from the real one",
+            policy.kind.name()
+        );
+    }
+    config.duress = Some(DuressConfig {
+        pin_hash: AuthConfig::hash_pin(&secret, &config.kdf)?,
+        hook,
+        marker_file,
+        stop_processes,
+    });
+    config.save(&path)?;
+    println!("Duress {} configured.", policy.kind.name());
+    Ok(())
+}
+
+/// Remove the duress PIN after checking the current PIN.
+pub fn remove_duress() -> Result<()> {
+    let path = get_config_path()?;
+    if !path.exists() {
+        anyhow::bail!("No PIN configured. Run 'agent-lock setup' first.");
     }
 
-    let config = AuthConfig::new(&pin);
+    let mut config = confirm_current(&path)?;
+    config.duress = None;
+    config.save(&path)?;
+    println!("Duress {} removed.", config.credential.kind.name());
+    Ok(())
+}
+
+/// Replace the recovery codes after checking the current PIN.
+pub fn new_recovery_codes() -> Result<()> {
     let path = get_config_path()?;
+    if !path.exists() {
+        anyhow::bail!("No PIN configured. Run 'agent-lock setup' first.");
+    }
+
+    let mut config = confirm_current(&path)?;
+    let codes = config.generate_recovery_codes()?;
     config.save(&path)?;
+    print_recovery_codes(&codes);
+    Ok(())
+}
+
+fn print_recovery_codes(codes: &[String]) {
+    println!(
+        "\nRecovery codes (each works once in place of the PIN or passphrase, also for 'agent-lock reset'):"
+    );
+    for code in codes {
+        println!("  {}", code);
+    }
+    println!("Store them somewhere safe; they are not shown again.");
+}
+
+/// Replace the PIN or passphrase after checking the current one, switching
+/// to `policy` if given. Wrong guesses count towards the same lockout as the
+/// lock screen.
+pub fn change_pin(policy: Option<CredentialPolicy>) -> Result<()> {
+    let path = get_config_path()?;
+    if !path.exists() {
+        anyhow::bail!("No PIN configured. Run 'agent-lock setup' first.");
+    }
+
+    let mut config = confirm_current(&path)?;
+    let policy = policy.unwrap_or_else(|| config.credential.clone());
+    let secret = read_new_secret(&policy, "New")?;
+    let name = policy.kind.name();
+    config.credential = policy;
+    config.set_pin(&secret)?;
+    config.save(&path)?;
+
+    println!("{} changed successfully!", capitalized(name));
+    Ok(())
+}
+
+/// Ask for the current PIN or passphrase and check it, returning the
+/// config it was checked against.
+fn confirm_current(path: &PathBuf) -> Result<AuthConfig> {
+    let kind = AuthConfig::load(path)?.credential.kind;
+    println!("Current {}:", kind.name());
+    let current = rpassword::read_password()?;
+    check_current_pin(path, &get_lockout_path()?, &current, unix_now())?;
+    AuthConfig::load(path)
+}
+
+/// Set a new PIN without the current one. The user proves they meant it
+/// with a recovery code, or by creating `reset-confirm` next to `auth.json`
+/// shortly before.
+pub fn reset_pin() -> Result<()> {
+    let path = get_config_path()?;
+    let lockout_path = get_lockout_path()?;
+    let confirm_path = path.with_file_name("reset-confirm");
+    let recovery_code = match check_reset_confirmation(&confirm_path, SystemTime::now()) {
+        Ok(()) => None,
+        Err(e) => {
+            let codes_left = AuthConfig::load(&path).map_or(0, |c| c.recovery_codes.len());
+            if codes_left == 0 {
+                println!("{:#}", e);
+                println!(
+                    "To reset the PIN without the current one, create {} \
+                     and run 'agent-lock reset' again within 10 minutes.",
+                    confirm_path.display()
+                );
+                anyhow::bail!("Reset not confirmed");
+            }
+
+            println!(
+                "Recovery code (or create {} to reset without one):",
+                confirm_path.display()
+            );
+            Some(rpassword::read_password()?)
+        }
+    };
+
+    // Keep the credential, KDF and lockout settings unless auth.json is
+    // unreadable. The new secret is read and hashed before a recovery code
+    // is used up, so a typo does not cost a code.
+    let config = match AuthConfig::load(&path) {
+        Ok(mut config) => {
+            let secret = read_new_secret(&config.credential, "New")?;
+            config.set_pin(&secret)?;
+            config
+        }
+        Err(_) => AuthConfig::new(&read_new_secret(&CredentialPolicy::default(), "New")?)?,
+    };
+    match recovery_code {
+        Some(code) => {
+            if !reset_with_recovery_code(&path, &lockout_path, &code, &config.pin_hash, unix_now())?
+            {
+                anyhow::bail!("Recovery code is incorrect");
+            }
+        }
+        None => {
+            config.save(&path)?;
+            fs::remove_file(&confirm_path).context("Failed to remove reset-confirm")?;
+            let mut state = LockoutState::load(&lockout_path).unwrap_or_default();
+            state.clear_failures();
+            state.save(&lockout_path)?;
+        }
+    }
+
+    println!(
+        "{} reset successfully!",
+        capitalized(config.credential.kind.name())
+    );
+    Ok(())
+}
+
+/// Cross off recovery `code` and store `pin_hash` in one save, so a code is
+/// only used up once the new PIN is in place. Counts towards the lockout
+/// like any other attempt.
+fn reset_with_recovery_code(
+    path: &PathBuf,
+    lockout_path: &Path,
+    code: &str,
+    pin_hash: &str,
+    now: u64,
+) -> Result<bool> {
+    check_attempt(path, lockout_path, now, |config| {
+        let matched = config.use_recovery_code(code);
+        if matched {
+            config.pin_hash = pin_hash.to_string();
+        }
+        matched
+    })
+}
+
+/// Ask for a new PIN or passphrase twice and check it against `policy`.
+/// `prompt` starts the question, e.g. "New" gives "New PIN (4-8 digits):".
+fn read_new_secret(policy: &CredentialPolicy, prompt: &str) -> Result<String> {
+    let name = policy.kind.name();
+    println!("{} {} ({}):", prompt, name, policy.describe());
+    let secret = rpassword::read_password()?;
+    policy.check(&secret)?;
+
+    println!("Confirm your {}:", name);
+    let confirm = rpassword::read_password()?;
+
+    if secret != confirm {
+        anyhow::bail!("The {}s do not match", name);
+    }
+    Ok(secret)
+}
+
+fn capitalized(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Check `pin` against the config at `path` for `change-pin`.
+fn check_current_pin(path: &PathBuf, lockout_path: &Path, pin: &str, now: u64) -> Result<()> {
+    if !check_attempt(path, lockout_path, now, |config| config.verify(pin))? {
+        let kind = AuthConfig::load(path)?.credential.kind;
+        anyhow::bail!("Current {} is incorrect", kind.name());
+    }
+    Ok(())
+}
+
+/// Run `attempt` against the config at `path` outside the lock screen,
+/// honouring and updating the lockout state like an unlock attempt does.
+/// On success the config is saved, persisting an upgraded hash or a used
+/// recovery code.
+fn check_attempt(
+    path: &PathBuf,
+    lockout_path: &Path,
+    now: u64,
+    attempt: impl FnOnce(&mut AuthConfig) -> bool,
+) -> Result<bool> {
+    let mut config = AuthConfig::load(path)?;
+    let mut state = LockoutState::load(lockout_path).unwrap_or_default();
+    if let Some(remaining) = state.remaining(now) {
+        anyhow::bail!(
+            "Too many failed attempts. Try again in {}",
+            format_duration(remaining.as_secs())
+        );
+    }
+
+    let matched = attempt(&mut config);
+    if matched {
+        config.save(path)?;
+        state.clear_failures();
+    } else {
+        state.record_failure(&config.lockout, now);
+    }
+    state.save(lockout_path)?;
+    Ok(matched)
+}
 
-    println!("PIN configured successfully!");
+fn check_reset_confirmation(confirm_path: &Path, now: SystemTime) -> Result<()> {
+    let modified = fs::metadata(confirm_path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("{} does not exist", confirm_path.display()))?;
+    let age = now.duration_since(modified).unwrap_or_default();
+    if age > RESET_CONFIRM_MAX_AGE {
+        anyhow::bail!(
+            "{} is older than 10 minutes; create it again",
+            confirm_path.display()
+        );
+    }
     Ok(())
 }
 
-pub fn verify_pin() -> Result<bool> {
-    let pin = rpassword::read_password()?;
-    Ok(verify_pin_internal(&pin))
+/// Verify what was typed on the lock screen, returning which credential it
+/// matched.
+pub fn verify_pin_internal(pin: &str) -> Option<Credential> {
+    let path = get_config_path().ok()?;
+    verify_pin_at(&path, pin)
+}
+
+fn verify_pin_at(path: &PathBuf, pin: &str) -> Option<Credential> {
+    verify_pin_with_lockout(path, &path.with_file_name("lockout.json"), pin, unix_now())
 }
 
-pub fn verify_pin_internal(pin: &str) -> bool {
-    match get_config_path() {
-        Ok(path) => match AuthConfig::load(&path) {
-            Ok(config) => config.verify(pin),
-            Err(_) => false,
-        },
-        Err(_) => false,
+/// Verify `pin`, refusing outright while a lockout is active and recording
+/// the outcome in the lockout state at `lockout_path`.
+pub fn verify_pin_with_lockout(
+    path: &PathBuf,
+    lockout_path: &Path,
+    pin: &str,
+    now: u64,
+) -> Option<Credential> {
+    let mut config = AuthConfig::load(path).ok()?;
+
+    let mut state = match LockoutState::load(lockout_path) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Failed to read lockout state, resetting it: {}", e);
+            LockoutState::default()
+        }
+    };
+    if state.remaining(now).is_some() {
+        return None;
+    }
+
+    let previous_hash = config.pin_hash.clone();
+    let mut matched = config.verify_unlock(pin, now);
+    if matched.is_some() && (config.pin_hash != previous_hash || config.totp.is_some()) {
+        if let Err(e) = config.save(path) {
+            eprintln!("Failed to save auth config: {}", e);
+        }
     }
+    if matched.is_none() && config.use_recovery_code(pin) {
+        // A code that cannot be crossed off must not unlock, or it could be
+        // used again.
+        match config.save(path) {
+            Ok(()) => {
+                matched = Some(Credential::RecoveryCode);
+                eprintln!(
+                    "Unlocked with a recovery code ({} left). Set a new PIN with 'agent-lock reset'.",
+                    config.recovery_codes.len()
+                );
+            }
+            Err(e) => eprintln!("Failed to consume recovery code: {}", e),
+        }
+    }
+
+    if matched.is_some() {
+        state = LockoutState {
+            last_unlock: Some(now),
+            ..LockoutState::default()
+        };
+    } else {
+        state.record_failure(&config.lockout, now);
+    }
+    if let Err(e) = state.save(lockout_path) {
+        eprintln!("Failed to save lockout state: {}", e);
+    }
+    matched
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn test_params() -> KdfParams {
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
     #[test]
-    fn test_hash_consistency() {
+    fn test_hash_is_salted() {
         let pin = "1234";
-        let hash1 = AuthConfig::hash_pin(pin);
-        let hash2 = AuthConfig::hash_pin(pin);
-        assert_eq!(hash1, hash2);
+        let hash1 = AuthConfig::hash_pin(pin, &test_params()).unwrap();
+        let hash2 = AuthConfig::hash_pin(pin, &test_params()).unwrap();
+        assert_ne!(hash1, hash2);
+        assert!(hash1.starts_with("$argon2id$"));
     }
 
     #[test]
     fn test_verify_correct_pin() {
-        let config = AuthConfig::new("1234");
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
         assert!(config.verify("1234"));
     }
 
     #[test]
     fn test_verify_incorrect_pin() {
-        let config = AuthConfig::new("1234");
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
         assert!(!config.verify("5678"));
     }
+
+    #[test]
+    fn test_legacy_hash_upgraded_on_correct_pin() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-auth-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let legacy = format!(
+            r#"{{"pin_hash": "{}", "kdf": {{"memory_kib": 64, "iterations": 1, "parallelism": 1}}}}"#,
+            AuthConfig::legacy_hash_pin("1234")
+        );
+        fs::write(&path, legacy).unwrap();
+
+        assert_eq!(verify_pin_at(&path, "0000"), None);
+        let legacy = AuthConfig::load(&path).unwrap();
+        assert_eq!(legacy.scheme(), HashScheme::LegacySha256);
+        assert_eq!(legacy.hash_version(), None);
+
+        assert_eq!(verify_pin_at(&path, "1234"), Some(Credential::Primary));
+        let upgraded = AuthConfig::load(&path).unwrap();
+        assert_eq!(upgraded.scheme(), HashScheme::Argon2id);
+        assert_eq!(upgraded.hash_version(), Some(19));
+        assert!(!upgraded.needs_rehash());
+        assert_eq!(verify_pin_at(&path, "1234"), Some(Credential::Primary));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_lockout_policy_backoff() {
+        let policy = LockoutPolicy::default();
+        let delays: Vec<u64> = (1..=11).map(|n| policy.delay_after(n)).collect();
+        assert_eq!(delays, vec![0, 0, 0, 5, 10, 20, 40, 80, 160, 900, 900]);
+
+        let capped = LockoutPolicy {
+            max_delay_secs: 30,
+            ..LockoutPolicy::default()
+        };
+        assert_eq!(capped.delay_after(9), 30);
+    }
+
+    #[test]
+    fn test_lockout_persists_and_resets() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-lockout-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let lockout_path = dir.join("lockout.json");
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
+        config.lockout = LockoutPolicy {
+            free_attempts: 1,
+            base_delay_secs: 10,
+            ..LockoutPolicy::default()
+        };
+        config.save(&path).unwrap();
+
+        let now = 1_000;
+        assert_eq!(
+            verify_pin_with_lockout(&path, &lockout_path, "0000", now),
+            None
+        );
+        assert_eq!(
+            verify_pin_with_lockout(&path, &lockout_path, "0000", now),
+            None
+        );
+
+        let state = LockoutState::load(&lockout_path).unwrap();
+        assert_eq!(state.failed_attempts, 2);
+        assert_eq!(state.remaining(now), Some(Duration::from_secs(10)));
+
+        // The correct PIN is refused while the lockout is active.
+        assert_eq!(
+            verify_pin_with_lockout(&path, &lockout_path, "1234", now + 5),
+            None
+        );
+        assert_eq!(
+            LockoutState::load(&lockout_path).unwrap().failed_attempts,
+            2
+        );
+
+        assert_eq!(
+            verify_pin_with_lockout(&path, &lockout_path, "1234", now + 10),
+            Some(Credential::Primary)
+        );
+        assert_eq!(
+            LockoutState::load(&lockout_path).unwrap(),
+            LockoutState {
+                last_unlock: Some(now + 10),
+                ..LockoutState::default()
+            }
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_change_pin_needs_current_pin() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-change-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let lockout_path = dir.join("lockout.json");
+        AuthConfig::with_params("1234", test_params())
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        let now = 1_000;
+        assert!(check_current_pin(&path, &lockout_path, "0000", now).is_err());
+        assert_eq!(
+            LockoutState::load(&lockout_path).unwrap().failed_attempts,
+            1
+        );
+
+        check_current_pin(&path, &lockout_path, "1234", now).unwrap();
+        assert_eq!(
+            LockoutState::load(&lockout_path).unwrap().failed_attempts,
+            0
+        );
+
+        let mut config = AuthConfig::load(&path).unwrap();
+        config.set_pin("567890").unwrap();
+        config.save(&path).unwrap();
+        assert_eq!(verify_pin_at(&path, "1234"), None);
+        assert_eq!(verify_pin_at(&path, "567890"), Some(Credential::Primary));
+
+        // The wait reads the same as on the lock screen.
+        LockoutState {
+            failed_attempts: 5,
+            locked_until: Some(now + 125),
+            last_unlock: None,
+        }
+        .save(&lockout_path)
+        .unwrap();
+        let error = check_current_pin(&path, &lockout_path, "567890", now).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Too many failed attempts. Try again in 2m 05s"
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reset_confirmation_must_be_fresh() {
+        let confirm_path =
+            std::env::temp_dir().join(format!("agent-lock-reset-confirm-{}", std::process::id()));
+        let now = SystemTime::now();
+        assert!(check_reset_confirmation(&confirm_path, now).is_err());
+
+        fs::write(&confirm_path, "").unwrap();
+        check_reset_confirmation(&confirm_path, now).unwrap();
+        assert!(
+            check_reset_confirmation(&confirm_path, now + Duration::from_secs(11 * 60)).is_err()
+        );
+
+        fs::remove_file(&confirm_path).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_codes_are_single_use() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-recovery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
+        let codes = config.generate_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), RECOVERY_CODE_LEN + 1);
+        assert!(config
+            .recovery_codes
+            .iter()
+            .all(|hash| !hash.contains(&codes[0])));
+        config.save(&path).unwrap();
+
+        let typed = codes[1].to_uppercase().replace('-', " ");
+        assert_eq!(verify_pin_at(&path, &typed), Some(Credential::RecoveryCode));
+        assert_eq!(
+            AuthConfig::load(&path).unwrap().recovery_codes.len(),
+            RECOVERY_CODE_COUNT - 1
+        );
+        assert_eq!(verify_pin_at(&path, &codes[1]), None);
+        assert_eq!(verify_pin_at(&path, "00000-00000"), None);
+        assert_eq!(verify_pin_at(&path, "1234"), Some(Credential::Primary));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reset_with_recovery_code() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-reset-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let lockout_path = dir.join("lockout.json");
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
+        let codes = config.generate_recovery_codes().unwrap();
+        config.save(&path).unwrap();
+        let new_hash = AuthConfig::hash_pin("5678", &config.kdf).unwrap();
+
+        assert!(
+            !reset_with_recovery_code(&path, &lockout_path, "00000-00000", &new_hash, 0).unwrap()
+        );
+        let unchanged = AuthConfig::load(&path).unwrap();
+        assert_eq!(unchanged.recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(unchanged.pin_hash, config.pin_hash);
+
+        assert!(reset_with_recovery_code(&path, &lockout_path, &codes[0], &new_hash, 0).unwrap());
+        let mut reset = AuthConfig::load(&path).unwrap();
+        assert_eq!(reset.recovery_codes.len(), RECOVERY_CODE_COUNT - 1);
+        assert!(reset.verify("5678"));
+        assert_eq!(
+            LockoutState::load(&lockout_path).unwrap().failed_attempts,
+            0
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_totp_unlock_modes() {
+        let secret = b"12345678901234567890";
+        let now = 1_111_111_109;
+        let code = totp::code_at(secret, totp::step_at(now));
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
+        config.totp = Some(TotpConfig {
+            secret: base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret),
+            mode: TotpMode::PinAndCode,
+            skew_steps: 1,
+            last_step: None,
+        });
+
+        assert_eq!(config.verify_unlock("1234", now), None);
+        assert_eq!(config.verify_unlock(&format!("0000{}", code), now), None);
+        assert_eq!(
+            config.verify_unlock(&format!("1234 {}", code), now),
+            Some(Credential::Primary)
+        );
+        // The same code cannot be replayed.
+        assert_eq!(config.verify_unlock(&format!("1234{}", code), now), None);
+
+        let next = totp::code_at(secret, totp::step_at(now) + 1);
+        config.totp.as_mut().unwrap().mode = TotpMode::CodeOnly;
+        assert_eq!(config.verify_unlock("1234", now), None);
+        assert_eq!(
+            config.verify_unlock(&next, now + 30),
+            Some(Credential::Primary)
+        );
+        assert_eq!(split_code("12"), None);
+    }
+
+    #[test]
+    fn test_duress_pin_unlocks_as_duress() {
+        let mut config = AuthConfig::with_params("1234", test_params()).unwrap();
+        config.duress = Some(DuressConfig {
+            pin_hash: AuthConfig::hash_pin("9999", &config.kdf).unwrap(),
+            hook: Vec::new(),
+            marker_file: None,
+            stop_processes: Vec::new(),
+        });
+        assert_eq!(config.verify_unlock("9999", 0), Some(Credential::Duress));
+        assert_eq!(config.verify_unlock("1234", 0), Some(Credential::Primary));
+        assert!(!config.verify("9999"));
+        assert!(config.set_pin("9999").is_err());
+
+        let secret = b"12345678901234567890";
+        let now = 1_111_111_109;
+        let code = totp::code_at(secret, totp::step_at(now));
+        config.totp = Some(TotpConfig {
+            secret: base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret),
+            mode: TotpMode::PinAndCode,
+            skew_steps: 1,
+            last_step: None,
+        });
+        assert_eq!(config.verify_unlock("9999", now), None);
+        assert_eq!(
+            config.verify_unlock(&format!("9999{}", code), now),
+            Some(Credential::Duress)
+        );
+    }
+
+    #[test]
+    fn test_credential_policy() {
+        let pin = CredentialPolicy::default();
+        assert!(pin.check("1234").is_ok());
+        assert!(pin.check("123").is_err());
+        assert!(pin.check("12a4").is_err());
+        assert_eq!(pin.describe(), "4-8 digits");
+
+        let passphrase = CredentialPolicy {
+            required_classes: vec![CharClass::Upper, CharClass::Symbol],
+            ..CredentialPolicy::passphrase()
+        };
+        assert!(passphrase.check("Correct horse!").is_ok());
+        assert!(passphrase.check("short A!").is_err());
+        assert!(passphrase.check("Grüße aus Köln!").is_err());
+        let error = passphrase.check("correct horse!").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The passphrase must contain an uppercase letter"
+        );
+        assert_eq!(
+            passphrase.describe(),
+            "12-100 characters with an uppercase letter, a symbol"
+        );
+
+        // Configs written before passphrase support hold a PIN.
+        let legacy: AuthConfig = serde_json::from_str(r#"{"pin_hash": "00"}"#).unwrap();
+        assert_eq!(legacy.credential, pin);
+    }
 }
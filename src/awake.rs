@@ -0,0 +1,278 @@
+use crate::process;
+use crate::session::{Session, SessionKind};
+use crate::sleep_prevention::{SleepPreventer, SleepProfile, SleepStatus};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(250);
+
+/// What `agent-lock awake` waits for before letting the machine sleep
+/// again. The first condition to end wins; with none, it runs until Ctrl+C.
+pub struct AwakeOptions {
+    pub duration: Option<Duration>,
+    pub until: Option<NaiveTime>,
+    pub while_pid: Option<u32>,
+    pub command: Vec<String>,
+    pub profile: SleepProfile,
+}
+
+/// Why sleep prevention was released.
+#[derive(Debug, PartialEq, Eq)]
+enum Ended {
+    TimeUp,
+    ProcessExited(u32),
+    CommandExited(Option<i32>),
+    Interrupted,
+}
+
+/// Keep the machine awake until one of the conditions in `options` ends.
+/// Returns the exit code of the wrapped command, or 0.
+pub fn run(options: AwakeOptions) -> Result<i32> {
+    let wait = [
+        options.duration,
+        options
+            .until
+            .map(|time| wait_until(Local::now().naive_local(), time)),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let deadline = wait
+        .map(|wait| ends_at(Instant::now(), Local::now(), wait))
+        .transpose()?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = Arc::clone(&running);
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .context("Error setting Ctrl-C handler")?;
+
+    let mut preventer = SleepPreventer::new(options.profile);
+    preventer.start()?;
+    if let SleepStatus::Unavailable(reason) = preventer.status() {
+        eprintln!("Warning: the machine may still sleep ({})", reason);
+    }
+    let session = Session::begin(SessionKind::Awake, &preventer);
+
+    let child = match options.command.split_first() {
+        Some((program, args)) => Some(
+            Command::new(program)
+                .args(args)
+                .spawn()
+                .with_context(|| format!("Failed to start {}", program))?,
+        ),
+        None => None,
+    };
+
+    let what = match options.profile {
+        SleepProfile::System => "system",
+        SleepProfile::Display => "system and display",
+        SleepProfile::Lid => "system (lid closed too)",
+        SleepProfile::DisplayLid => "system and display (lid closed too)",
+    };
+    println!(
+        "Keeping the {} awake{}",
+        what,
+        describe(&options, deadline.map(|(_, end)| end))
+    );
+
+    let mut watch = Watch {
+        deadline: deadline.map(|(deadline, _)| deadline),
+        pid: options.while_pid,
+        child,
+    };
+    let ended = loop {
+        if !running.load(Ordering::SeqCst) {
+            break Ended::Interrupted;
+        }
+        if let Some(ended) = watch.check(Instant::now())? {
+            break ended;
+        }
+        std::thread::sleep(TICK);
+    };
+
+    drop(session);
+    preventer.stop();
+    let (reason, code) = match ended {
+        Ended::TimeUp => ("time is up".to_string(), 0),
+        Ended::ProcessExited(pid) => (format!("process {} exited", pid), 0),
+        Ended::CommandExited(code) => ("command finished".to_string(), code.unwrap_or(1)),
+        Ended::Interrupted => ("interrupted".to_string(), 0),
+    };
+    println!("Sleep prevention released: {}", reason);
+    Ok(code)
+}
+
+/// Parse a wall-clock time such as `18:00`.
+pub fn parse_clock_time(text: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(text.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time '{}': expected HH:MM, e.g. 18:00", text))
+}
+
+/// Time from `now` to the next `time` of day, tomorrow if it already passed.
+fn wait_until(now: NaiveDateTime, time: NaiveTime) -> Duration {
+    let mut target = now.date().and_time(time);
+    if target <= now {
+        target += chrono::Duration::days(1);
+    }
+    (target - now).to_std().unwrap_or_default()
+}
+
+/// When a `wait` started at `now` (and wall-clock `local`) ends, refusing
+/// waits too long for either clock to reach.
+fn ends_at(
+    now: Instant,
+    local: DateTime<Local>,
+    wait: Duration,
+) -> Result<(Instant, DateTime<Local>)> {
+    now.checked_add(wait)
+        .zip(
+            chrono::Duration::from_std(wait)
+                .ok()
+                .and_then(|wait| local.checked_add_signed(wait)),
+        )
+        .with_context(|| format!("Duration of {}s is too long", wait.as_secs()))
+}
+
+fn describe(options: &AwakeOptions, end: Option<DateTime<Local>>) -> String {
+    let mut parts = Vec::new();
+    if let Some(end) = end {
+        parts.push(format!("until {}", end.format("%H:%M:%S")));
+    }
+    if let Some(pid) = options.while_pid {
+        parts.push(format!("while process {} runs", pid));
+    }
+    if let Some(program) = options.command.first() {
+        parts.push(format!("while {} runs", program));
+    }
+    if parts.is_empty() {
+        " until Ctrl+C".to_string()
+    } else {
+        format!(" {}", parts.join(" and "))
+    }
+}
+
+struct Watch {
+    deadline: Option<Instant>,
+    pid: Option<u32>,
+    child: Option<Child>,
+}
+
+impl Watch {
+    fn check(&mut self, now: Instant) -> Result<Option<Ended>> {
+        if let Some(child) = self.child.as_mut() {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(Ended::CommandExited(status.code())));
+            }
+        }
+        if let Some(pid) = self.pid {
+            if !process::alive(pid) {
+                return Ok(Some(Ended::ProcessExited(pid)));
+            }
+        }
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            return Ok(Some(Ended::TimeUp));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_wait_until_next_occurrence() {
+        let now = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(17, 30, 0)
+            .unwrap();
+        let six = parse_clock_time("18:00").unwrap();
+        assert_eq!(wait_until(now, six), Duration::from_secs(30 * 60));
+
+        let nine = parse_clock_time("09:00").unwrap();
+        assert_eq!(
+            wait_until(now, nine),
+            Duration::from_secs(15 * 3600 + 30 * 60)
+        );
+
+        assert!(parse_clock_time("25:00").is_err());
+        assert!(parse_clock_time("6pm").is_err());
+    }
+
+    #[test]
+    fn test_enormous_wait_is_too_long() {
+        let now = Instant::now();
+        let local = Local::now();
+        let (deadline, end) = ends_at(now, local, Duration::from_secs(90)).unwrap();
+        assert_eq!(deadline - now, Duration::from_secs(90));
+        assert_eq!(end - local, chrono::Duration::seconds(90));
+
+        // `awake --for 500000000000h` fails before anything is kept awake.
+        let options = AwakeOptions {
+            duration: Some(crate::duration::parse_duration("500000000000h").unwrap()),
+            until: None,
+            while_pid: None,
+            command: Vec::new(),
+            profile: SleepProfile::System,
+        };
+        let error = run(options).unwrap_err().to_string();
+        assert!(error.ends_with("is too long"), "{}", error);
+        assert!(ends_at(now, local, Duration::MAX).is_err());
+    }
+
+    #[test]
+    fn test_watch_deadline() {
+        let start = Instant::now();
+        let mut watch = Watch {
+            deadline: Some(start + Duration::from_secs(60)),
+            pid: None,
+            child: None,
+        };
+        assert_eq!(watch.check(start).unwrap(), None);
+        assert_eq!(
+            watch.check(start + Duration::from_secs(60)).unwrap(),
+            Some(Ended::TimeUp)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watch_ends_with_process_and_command() {
+        let mut exited = Command::new("true").spawn().unwrap();
+        let pid = exited.id();
+        exited.wait().unwrap();
+        assert!(process::alive(std::process::id()));
+
+        let mut watch = Watch {
+            deadline: None,
+            pid: Some(pid),
+            child: None,
+        };
+        assert_eq!(
+            watch.check(Instant::now()).unwrap(),
+            Some(Ended::ProcessExited(pid))
+        );
+
+        let mut watch = Watch {
+            deadline: None,
+            pid: None,
+            child: Some(Command::new("sh").args(["-c", "exit 7"]).spawn().unwrap()),
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let ended = loop {
+            if let Some(ended) = watch.check(Instant::now()).unwrap() {
+                break ended;
+            }
+            assert!(Instant::now() < deadline, "command never finished");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(ended, Ended::CommandExited(Some(7)));
+    }
+}
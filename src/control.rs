@@ -0,0 +1,427 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// How long a connection waits for the daemon loop, and for a client to
+/// send its request, before giving up.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request to the daemon, sent as one line of JSON such as
+/// `{"command":"lock"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::Subcommand)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    /// Lock the screen now
+    Lock,
+    /// Show whether the screen is locked and which hotkey is registered
+    Status,
+    /// Stop the daemon
+    Stop,
+    /// Re-read daemon.json and register its hotkey
+    Reload,
+}
+
+/// The daemon's answer, also one line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DaemonStatus>,
+}
+
+impl Response {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+            status: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+            status: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub locked: bool,
+    pub lock_pid: Option<u32>,
+    pub hotkey: String,
+    pub idle_lock_secs: Option<u64>,
+}
+
+/// A request waiting for the daemon loop to answer it.
+pub struct Pending {
+    pub request: Request,
+    reply: Sender<Response>,
+}
+
+impl Pending {
+    pub fn respond(self, response: Response) {
+        // The client may have given up already; nothing to do then.
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Accepts control connections on a background thread and hands their
+/// requests to the daemon loop, which polls [`ControlServer::try_recv`]
+/// next to the hotkey receiver.
+pub struct ControlServer {
+    requests: Receiver<Pending>,
+    #[cfg(unix)]
+    socket: std::path::PathBuf,
+}
+
+impl ControlServer {
+    /// Start listening. Fails if another daemon is already listening.
+    pub fn start() -> Result<Self> {
+        let (sender, requests) = mpsc::channel();
+        #[cfg(unix)]
+        let socket = platform::listen(sender)?;
+        #[cfg(target_os = "windows")]
+        platform::listen(sender)?;
+        Ok(Self {
+            requests,
+            #[cfg(unix)]
+            socket,
+        })
+    }
+
+    pub fn try_recv(&self) -> Option<Pending> {
+        self.requests.try_recv().ok()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// Where clients connect: a socket file on Unix, a named pipe on Windows.
+pub fn address() -> Result<String> {
+    platform::address()
+}
+
+/// Send `request` to the running daemon and wait for its answer.
+pub fn send(request: Request) -> Result<Response> {
+    let mut stream = platform::connect()?;
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("Failed to read the daemon's response")?;
+    serde_json::from_str(&line).context("Invalid response from daemon")
+}
+
+/// Answer one connection: read a request line, pass it to the daemon loop
+/// and write back its response.
+fn serve<S: Read + Write>(stream: S, requests: &Sender<Pending>) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let (reply, answer) = mpsc::channel();
+            requests
+                .send(Pending { request, reply })
+                .context("Daemon is shutting down")?;
+            answer
+                .recv_timeout(REPLY_TIMEOUT)
+                .unwrap_or_else(|_| Response::error("Daemon did not answer in time"))
+        }
+        Err(e) => Response::error(format!("Invalid request: {}", e)),
+    };
+
+    let mut stream = reader.into_inner();
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{serve, Pending, REPLY_TIMEOUT};
+    use anyhow::{bail, Context, Result};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::Sender;
+
+    fn socket_path() -> Result<PathBuf> {
+        Ok(crate::auth::get_config_path()?.with_file_name("control.sock"))
+    }
+
+    pub fn address() -> Result<String> {
+        Ok(socket_path()?.display().to_string())
+    }
+
+    /// Bind the socket, readable and writable by the owner only, and serve
+    /// connections on a background thread.
+    pub fn listen(requests: Sender<Pending>) -> Result<PathBuf> {
+        let path = socket_path()?;
+        listen_at(&path, requests)?;
+        Ok(path)
+    }
+
+    pub(super) fn listen_at(path: &Path, requests: Sender<Pending>) -> Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("Another daemon is already listening on {}", path.display());
+            }
+            // Left behind by a daemon that did not shut down cleanly.
+            fs::remove_file(path).context("Failed to remove stale control socket")?;
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // The socket is created owner-only, so no other user can connect
+        // before its permissions are set.
+        let umask = unsafe { libc::umask(0o077) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(umask) };
+        let listener = listener
+            .with_context(|| format!("Failed to create control socket {}", path.display()))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        // Each connection gets its own thread, so a client that never sends
+        // its request does not hold up the others.
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let requests = requests.clone();
+                std::thread::spawn(move || {
+                    let _ = stream.set_read_timeout(Some(REPLY_TIMEOUT));
+                    if let Err(e) = serve(stream, &requests) {
+                        eprintln!("Control request failed: {:#}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    pub fn connect() -> Result<UnixStream> {
+        let path = socket_path()?;
+        let stream = UnixStream::connect(&path).with_context(|| {
+            format!(
+                "Daemon is not running (no control socket at {})",
+                path.display()
+            )
+        })?;
+        // A wedged daemon must not hang `ctl` or `status`; the server gives up
+        // on its loop after REPLY_TIMEOUT, so allow a little more than that.
+        stream.set_read_timeout(Some(REPLY_TIMEOUT * 2))?;
+        Ok(stream)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::{serve, Pending};
+    use anyhow::{bail, Context, Result};
+    use std::fs::{File, OpenOptions};
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::sync::mpsc::Sender;
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE};
+    use windows::Win32::Storage::FileSystem::{
+        FlushFileBuffers, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    };
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+        PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    fn pipe_name() -> String {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\agent-lock-{}", user)
+    }
+
+    pub fn address() -> Result<String> {
+        Ok(pipe_name())
+    }
+
+    /// Create the pipe and serve connections on a background thread, one
+    /// pipe instance per client.
+    pub fn listen(requests: Sender<Pending>) -> Result<()> {
+        let name: Vec<u16> = pipe_name().encode_utf16().chain(Some(0)).collect();
+        // Only the first instance may claim the name, so a second daemon
+        // (or another program squatting on it) is detected here.
+        let first = create_instance(&name, true)
+            .context("Another daemon is already listening on the control pipe")?;
+
+        std::thread::spawn(move || {
+            let mut next = Some(first);
+            loop {
+                let handle = match next.take() {
+                    Some(handle) => handle,
+                    None => match create_instance(&name, false) {
+                        Ok(handle) => handle,
+                        Err(e) => {
+                            eprintln!("Control pipe failed: {:#}", e);
+                            return;
+                        }
+                    },
+                };
+                let connected = match unsafe { ConnectNamedPipe(handle, None) } {
+                    Ok(()) => true,
+                    Err(e) => e.code() == ERROR_PIPE_CONNECTED.to_hresult(),
+                };
+                let pipe = unsafe { File::from_raw_handle(handle.0 as _) };
+                if !connected {
+                    continue;
+                }
+                // Each client is served on its own thread, so one that never
+                // sends its request does not hold up the others.
+                let requests = requests.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = serve(&pipe, &requests) {
+                        eprintln!("Control request failed: {:#}", e);
+                    }
+                    let handle = HANDLE(pipe.as_raw_handle() as _);
+                    unsafe {
+                        let _ = FlushFileBuffers(handle);
+                        let _ = DisconnectNamedPipe(handle);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn create_instance(name: &[u16], first: bool) -> Result<HANDLE> {
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                PCWSTR(name.as_ptr()),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                4096,
+                4096,
+                0,
+                None,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            bail!(
+                "Failed to create control pipe: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(handle)
+    }
+
+    pub fn connect() -> Result<File> {
+        let name = pipe_name();
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&name)
+            .with_context(|| format!("Daemon is not running (no control pipe at {})", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_format() {
+        assert_eq!(
+            serde_json::to_string(&Request::Lock).unwrap(),
+            r#"{"command":"lock"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command":"reload"}"#).unwrap(),
+            Request::Reload
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command":"unlock"}"#).is_err());
+        assert_eq!(
+            serde_json::to_string(&Response::ok("Screen locked")).unwrap(),
+            r#"{"ok":true,"message":"Screen locked"}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_round_trip() {
+        use std::os::unix::net::UnixStream;
+
+        let (sender, requests) = mpsc::channel();
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let connection = std::thread::spawn(move || serve(server, &sender));
+
+        writeln!(client, r#"{{"command":"status"}}"#).unwrap();
+        let pending = requests.recv_timeout(REPLY_TIMEOUT).unwrap();
+        assert_eq!(pending.request, Request::Status);
+        pending.respond(Response::ok("idle"));
+        connection.join().unwrap().unwrap();
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Response>(&line).unwrap(),
+            Response::ok("idle")
+        );
+
+        let (sender, _requests) = mpsc::channel();
+        let (mut client, server) = UnixStream::pair().unwrap();
+        writeln!(client, "not json").unwrap();
+        serve(server, &sender).unwrap();
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert!(!serde_json::from_str::<Response>(&line).unwrap().ok);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_is_private_and_serves_clients_in_parallel() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let dir = std::env::temp_dir().join(format!("agent-lock-control-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+        let (sender, requests) = mpsc::channel();
+        platform::listen_at(&path, sender).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A client that connects and never writes does not block the next.
+        let _idle = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, r#"{{"command":"status"}}"#).unwrap();
+        let pending = requests.recv_timeout(REPLY_TIMEOUT / 2).unwrap();
+        assert_eq!(pending.request, Request::Status);
+        pending.respond(Response::ok("idle"));
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Response>(&line).unwrap(),
+            Response::ok("idle")
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,230 @@
+use crate::control::{ControlServer, DaemonStatus, Request, Response};
+use crate::hotkey::{self, DaemonConfig};
+use crate::idle::{self, IdleLock};
+use crate::session::{self, SessionKind};
+use anyhow::{Context, Result};
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager};
+use std::path::Path;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(100);
+const IDLE_POLL: Duration = Duration::from_secs(1);
+
+/// Wait for the lock hotkey (and, with `idle_lock`, for the user to go idle)
+/// and run `agent-lock lock` as a child process each time. Hotkeys come from
+/// `cli_hotkeys`, or from `daemon.json` when none were given, and are tried
+/// in order until one registers. Only one lock runs at a time, counting locks
+/// started outside the daemon. Requests from
+/// `agent-lock ctl` arrive over the control socket.
+pub fn run(cli_hotkeys: Vec<String>, idle_lock: Option<Duration>) -> Result<()> {
+    println!("Starting agent-lock daemon...");
+    if let Some(threshold) = idle_lock {
+        println!("Locking after {}s without input", threshold.as_secs());
+    }
+    println!("Press the hotkey below to lock screen, Ctrl+C to quit daemon");
+    println!();
+    println!("Note: If hotkey doesn't work, grant Accessibility permissions:");
+    println!("  System Settings → Privacy & Security → Accessibility");
+    println!();
+
+    let hotkeys = hotkey::parse_hotkeys(&configured_hotkeys(&cli_hotkeys)?)?;
+    let manager = GlobalHotKeyManager::new()
+        .context("Failed to create hotkey manager - may need Accessibility permissions")?;
+
+    let mut registered = match register_first(&manager, &hotkeys) {
+        Ok(registered) => registered,
+        Err(e) => {
+            println!("\nTroubleshooting:");
+            println!("1. Grant Accessibility permissions:");
+            println!("   System Settings → Privacy & Security → Accessibility");
+            println!("2. Add Terminal.app (or your terminal) to allowed apps");
+            println!("3. Restart terminal and try again");
+            println!("\nNote: Some apps may conflict with global hotkeys");
+            return Err(e);
+        }
+    };
+
+    let control = ControlServer::start()?;
+    println!("Control socket: {}", crate::control::address()?);
+
+    let mut idle = match idle_lock {
+        Some(threshold) => Some(IdleLock::new(idle::default_source()?, threshold)),
+        None => None,
+    };
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = Arc::clone(&running);
+
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .context("Error setting Ctrl-C handler")?;
+
+    let receiver = GlobalHotKeyEvent::receiver();
+    let exe_path = std::env::current_exe()?;
+    let mut lock: Option<Child> = None;
+    let mut last_idle_check = Instant::now();
+
+    while running.load(Ordering::SeqCst) {
+        if let Some(child) = lock.as_mut() {
+            if !matches!(child.try_wait(), Ok(None)) {
+                println!("Screen unlocked");
+                lock = None;
+            }
+        }
+
+        if receiver.try_recv().is_ok() && lock.is_none() {
+            println!("Hotkey triggered - locking screen...");
+            lock = spawn_lock(&exe_path);
+        }
+
+        while let Some(pending) = control.try_recv() {
+            let response = match pending.request {
+                Request::Lock if lock.is_some() || locked_elsewhere().is_some() => {
+                    Response::ok("Screen is already locked")
+                }
+                Request::Lock => {
+                    println!("Lock requested over the control socket - locking screen...");
+                    lock = spawn_lock(&exe_path);
+                    if lock.is_some() {
+                        Response::ok("Screen locked")
+                    } else {
+                        Response::error("Failed to start the lock screen")
+                    }
+                }
+                Request::Status => Response {
+                    status: Some(DaemonStatus {
+                        pid: std::process::id(),
+                        locked: lock.is_some(),
+                        lock_pid: lock.as_ref().map(Child::id),
+                        hotkey: registered.0.clone(),
+                        idle_lock_secs: idle_lock.map(|threshold| threshold.as_secs()),
+                    }),
+                    ..Response::ok(if lock.is_some() { "locked" } else { "unlocked" })
+                },
+                Request::Stop => {
+                    running.store(false, Ordering::SeqCst);
+                    Response::ok("Daemon stopping")
+                }
+                Request::Reload => match reload(&manager, &mut registered, &cli_hotkeys) {
+                    Ok(message) => Response::ok(message),
+                    Err(e) => Response::error(format!("{:#}", e)),
+                },
+            };
+            pending.respond(response);
+        }
+
+        if let Some(idle) = idle.as_mut() {
+            if last_idle_check.elapsed() >= IDLE_POLL {
+                last_idle_check = Instant::now();
+                match idle.should_lock(lock.is_some()) {
+                    Ok(true) => {
+                        println!("Idle timeout reached - locking screen...");
+                        lock = spawn_lock(&exe_path);
+                    }
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to read idle time: {:#}", e),
+                }
+            }
+        }
+
+        std::thread::sleep(TICK);
+    }
+
+    manager.unregister(registered.1)?;
+    println!("\nDaemon stopped");
+    Ok(())
+}
+
+fn configured_hotkeys(cli_hotkeys: &[String]) -> Result<Vec<String>> {
+    if cli_hotkeys.is_empty() {
+        Ok(DaemonConfig::load(&hotkey::get_daemon_config_path()?)?.hotkeys)
+    } else {
+        Ok(cli_hotkeys.to_vec())
+    }
+}
+
+/// Register the first hotkey the system accepts, in configured order.
+fn register_first(
+    manager: &GlobalHotKeyManager,
+    hotkeys: &[(String, HotKey)],
+) -> Result<(String, HotKey)> {
+    let mut last_error = None;
+    for (index, (text, hotkey)) in hotkeys.iter().enumerate() {
+        match manager.register(*hotkey) {
+            Ok(_) => {
+                if index == 0 {
+                    println!("✓ Hotkey registered: {}", text);
+                } else {
+                    println!("✓ Hotkey registered: {} (fallback)", text);
+                }
+                return Ok((text.clone(), *hotkey));
+            }
+            Err(e) => {
+                println!("✗ Could not register {}: {}", text, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error
+        .map(Into::into)
+        .unwrap_or_else(|| anyhow::anyhow!("No hotkey registered")))
+}
+
+/// Swap the registered hotkey for the one `daemon.json` now names. On any
+/// error the previous hotkey stays registered.
+fn reload(
+    manager: &GlobalHotKeyManager,
+    registered: &mut (String, HotKey),
+    cli_hotkeys: &[String],
+) -> Result<String> {
+    if !cli_hotkeys.is_empty() {
+        return Ok("Hotkeys were given with --hotkey; nothing to reload".to_string());
+    }
+    let hotkeys = hotkey::parse_hotkeys(&configured_hotkeys(cli_hotkeys)?)?;
+    if hotkeys.first().map(|(_, hotkey)| hotkey) == Some(&registered.1) {
+        return Ok(format!("Hotkey unchanged: {}", registered.0));
+    }
+
+    manager.unregister(registered.1)?;
+    match register_first(manager, &hotkeys) {
+        Ok(new) => {
+            *registered = new;
+            Ok(format!("Hotkey registered: {}", registered.0))
+        }
+        Err(e) => {
+            manager.register(registered.1)?;
+            Err(e.context(format!("Kept {}", registered.0)))
+        }
+    }
+}
+
+/// The PID of a lock this daemon did not start, such as `agent-lock lock`
+/// run from a terminal, if one is on screen.
+fn locked_elsewhere() -> Option<u32> {
+    let dir = session::get_sessions_dir().ok()?;
+    session::running(&dir)
+        .into_iter()
+        .find(|record| record.kind == SessionKind::Lock && record.pid != std::process::id())
+        .map(|record| record.pid)
+}
+
+/// Start `agent-lock lock`, unless a lock is already on screen: a second one
+/// would stack on top of it.
+fn spawn_lock(exe_path: &Path) -> Option<Child> {
+    if let Some(pid) = locked_elsewhere() {
+        println!("Screen is already locked (process {})", pid);
+        return None;
+    }
+    match Command::new(exe_path).arg("lock").spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("Failed to spawn lock process: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+/// Parse a duration written as a number followed by `s`, `m` or `h`, such as
+/// `90s`, `10m` or `1h30m`. A bare number is taken as seconds.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    if text.is_empty() {
+        bail!("Empty duration");
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            _ => bail!(
+                "Invalid duration '{}': unknown unit '{}' (use s, m or h)",
+                text,
+                c
+            ),
+        };
+        if number.is_empty() {
+            bail!(
+                "Invalid duration '{}': expected a number before '{}'",
+                text,
+                c
+            );
+        }
+        total = add_part(text, total, &number, unit)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = add_part(text, total, &number, 1)?;
+    }
+
+    if total == 0 {
+        bail!("Duration '{}' must be longer than zero", text);
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// `total` plus `number` of `unit` seconds, refusing durations too long to
+/// count.
+fn add_part(text: &str, total: u64, number: &str, unit: u64) -> Result<u64> {
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(unit))
+        .and_then(|secs| total.checked_add(secs))
+        .ok_or_else(|| anyhow::anyhow!("Duration '{}' is too long", text))
+}
+
+/// `secs` as a short human-readable duration, e.g. `2m 05s` or `3h 07m`.
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_overflow() {
+        let max = u64::MAX.to_string();
+        assert_eq!(parse_duration(&max).unwrap(), Duration::from_secs(u64::MAX));
+        for text in [
+            format!("{}h", u64::MAX / 3600 + 1),
+            format!("{}s1s", u64::MAX),
+            format!("{}0", u64::MAX),
+        ] {
+            let error = parse_duration(&text).unwrap_err().to_string();
+            assert_eq!(error, format!("Duration '{}' is too long", text));
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(125), "2m 05s");
+        assert_eq!(format_duration(3 * 3600 + 7 * 60), "3h 07m");
+    }
+}
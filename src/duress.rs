@@ -0,0 +1,129 @@
+use crate::auth::DuressConfig;
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use sysinfo::{Pid, Signal, System};
+
+/// Carry out the duress actions after the duress PIN unlocked the screen.
+///
+/// Nothing may show that anything unusual happened, so no action prints to
+/// the terminal; failures go to `duress.log` next to `auth.json` instead.
+pub fn trigger(config: &DuressConfig) {
+    let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let mut failures = Vec::new();
+
+    if let Some(marker) = &config.marker_file {
+        if let Err(e) = write_marker(marker, &time) {
+            failures.push(format!("{:#}", e));
+        }
+    }
+    if !config.hook.is_empty() {
+        if let Err(e) = start_hook(&config.hook) {
+            failures.push(format!("{:#}", e));
+        }
+    }
+    if !config.stop_processes.is_empty() {
+        let mut system = System::new();
+        system.refresh_processes();
+        for target in &config.stop_processes {
+            if let Err(e) = stop_processes(&system, target) {
+                failures.push(format!("{:#}", e));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        let _ = log_failures(&time, &failures);
+    }
+}
+
+fn write_marker(path: &Path, time: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("duress unlock at {}\n", time))
+        .with_context(|| format!("Failed to write marker file {}", path.display()))
+}
+
+/// Start `hook` detached from the terminal, with `AGENT_LOCK_EVENT=duress`
+/// in its environment, and reap it in the background.
+fn start_hook(hook: &[String]) -> Result<()> {
+    let (program, args) = hook.split_first().context("Empty hook command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .env("AGENT_LOCK_EVENT", "duress")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to start hook {}", program))?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Stop the process with PID `target`, or every process named `target`:
+/// politely where the platform allows, otherwise by killing it.
+fn stop_processes(system: &System, target: &str) -> Result<()> {
+    let processes: Vec<_> = match target.parse::<u32>() {
+        Ok(pid) => system.process(Pid::from_u32(pid)).into_iter().collect(),
+        Err(_) => system
+            .processes()
+            .values()
+            .filter(|process| process.name() == target)
+            .collect(),
+    };
+    if processes.is_empty() {
+        anyhow::bail!("No process {} to stop", target);
+    }
+    for process in processes {
+        let stopped = process
+            .kill_with(Signal::Term)
+            .unwrap_or_else(|| process.kill());
+        if !stopped {
+            anyhow::bail!("Failed to stop {} ({})", process.name(), process.pid());
+        }
+    }
+    Ok(())
+}
+
+fn log_failures(time: &str, failures: &[String]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_duress_log_path()?)?;
+    for failure in failures {
+        writeln!(file, "{} {}", time, failure)?;
+    }
+    Ok(())
+}
+
+fn get_duress_log_path() -> Result<PathBuf> {
+    Ok(crate::auth::get_config_path()?.with_file_name("duress.log"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_written_and_missing_process_reported() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-duress-{}", std::process::id()));
+        let marker = dir.join("nested").join("duress");
+
+        write_marker(&marker, "2026-01-01 12:00:00").unwrap();
+        assert_eq!(
+            fs::read_to_string(&marker).unwrap(),
+            "duress unlock at 2026-01-01 12:00:00\n"
+        );
+
+        let system = System::new();
+        assert!(stop_processes(&system, "agent-lock-no-such-process").is_err());
+        assert!(start_hook(&[]).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,365 @@
+//! Past lock sessions, pieced together from the audit log.
+
+use crate::audit::{AuditEntry, AuditEvent};
+use crate::auth::Credential;
+use crate::duration::format_duration;
+use crate::session::{SessionKind, SessionRecord};
+use chrono::{Datelike, Local, NaiveDate, NaiveTime, TimeZone};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// How a lock session ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    Unlocked,
+    /// The lock went away without being unlocked: killed, crashed, or the
+    /// machine shut down.
+    Interrupted,
+    Running,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockRecord {
+    pub pid: u32,
+    /// Unix timestamps (seconds). An interrupted session ends at its last
+    /// entry in the log, a running one now.
+    pub started: u64,
+    pub ended: u64,
+    pub duration_secs: u64,
+    pub failed_attempts: u32,
+    pub ending: Ending,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<Credential>,
+}
+
+/// The lock sessions in `entries`, oldest first. `running` tells whether the
+/// lock with a PID that started at a time is still on screen.
+pub fn sessions(
+    entries: &[AuditEntry],
+    now: u64,
+    running: impl Fn(u32, u64) -> bool,
+) -> Vec<LockRecord> {
+    let mut records: Vec<LockRecord> = Vec::new();
+    let mut open: HashMap<u32, usize> = HashMap::new();
+
+    for entry in entries {
+        let pid = entry.pid();
+        let time = entry.time();
+        match entry.event() {
+            AuditEvent::LockStarted => {
+                // A PID can only be reused once its earlier lock is gone.
+                open.insert(pid, records.len());
+                records.push(LockRecord {
+                    pid,
+                    started: time,
+                    ended: time,
+                    duration_secs: 0,
+                    failed_attempts: 0,
+                    ending: Ending::Interrupted,
+                    method: None,
+                });
+            }
+            AuditEvent::UnlockFailed => {
+                if let Some(&index) = open.get(&pid) {
+                    records[index].failed_attempts += 1;
+                    records[index].ended = time;
+                }
+            }
+            AuditEvent::Unlocked {
+                method,
+                duration_secs,
+            } => {
+                if let Some(index) = open.remove(&pid) {
+                    let record = &mut records[index];
+                    record.ended = time;
+                    record.duration_secs = *duration_secs;
+                    record.ending = Ending::Unlocked;
+                    record.method = Some(*method);
+                }
+            }
+        }
+    }
+
+    for (pid, index) in open {
+        let record = &mut records[index];
+        if running(pid, record.started) {
+            record.ending = Ending::Running;
+            record.ended = now.max(record.ended);
+        }
+        record.duration_secs = record.ended - record.started;
+    }
+    records
+}
+
+/// Whether one of the running `sessions` is the lock with `pid` that started
+/// at `started`. A PID alone is not enough: after a crash or reboot another
+/// process may have it.
+pub fn lock_running(sessions: &[SessionRecord], pid: u32, started: u64) -> bool {
+    // The session record is written just after the lock's first audit entry.
+    sessions
+        .iter()
+        .any(|s| s.kind == SessionKind::Lock && s.pid == pid && s.started >= started)
+}
+
+/// Print `records` newest first as a table.
+pub fn print_history(records: &[LockRecord]) {
+    if records.is_empty() {
+        println!("No lock sessions recorded yet.");
+        return;
+    }
+    println!("{:<17}  {:>8}  {:>6}  Ended", "Started", "Locked", "Failed");
+    for record in records.iter().rev() {
+        let ending = match (record.ending, record.method) {
+            (Ending::Unlocked, Some(Credential::RecoveryCode)) => "unlocked (recovery code)",
+            (Ending::Unlocked, _) => "unlocked",
+            (Ending::Interrupted, _) => "interrupted",
+            (Ending::Running, _) => "still locked",
+        };
+        println!(
+            "{:<17}  {:>8}  {:>6}  {}",
+            local_time(record.started),
+            format_duration(record.duration_secs),
+            record.failed_attempts,
+            ending
+        );
+    }
+}
+
+/// Time locked in one day (`2026-10-12`) or ISO week (`2026-W41`).
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeriodTotal {
+    pub period: String,
+    pub locked_secs: u64,
+}
+
+/// Totals over the sessions since a point in time, for `agent-lock stats`.
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub since: u64,
+    pub sessions: usize,
+    pub locked_secs: u64,
+    pub longest: Option<LockRecord>,
+    pub unlocks: usize,
+    pub failed_attempts: u32,
+    /// Failed attempts as a share of all unlock attempts, from 0 to 1.
+    pub failure_rate: f64,
+    pub per_day: Vec<PeriodTotal>,
+    pub per_week: Vec<PeriodTotal>,
+}
+
+impl Stats {
+    /// Totals over the part of `records` after `since`, with days and weeks
+    /// counted in `tz`. A session over midnight counts towards both days.
+    pub fn compute<Tz: TimeZone>(records: &[LockRecord], since: u64, tz: &Tz) -> Self {
+        let records: Vec<&LockRecord> = records.iter().filter(|r| r.ended > since).collect();
+        let mut days: BTreeMap<NaiveDate, u64> = BTreeMap::new();
+        for record in &records {
+            let start = record.started.max(since);
+            let end = (record.started + record.duration_secs).max(start);
+            for (day, secs) in split_by_day(start, end, tz) {
+                *days.entry(day).or_default() += secs;
+            }
+        }
+        let mut weeks: BTreeMap<(i32, u32), u64> = BTreeMap::new();
+        for (day, secs) in &days {
+            let week = day.iso_week();
+            *weeks.entry((week.year(), week.week())).or_default() += secs;
+        }
+
+        let unlocks = records
+            .iter()
+            .filter(|r| r.ending == Ending::Unlocked)
+            .count();
+        let failed_attempts: u32 = records.iter().map(|r| r.failed_attempts).sum();
+        let attempts = unlocks as u64 + u64::from(failed_attempts);
+        Self {
+            since,
+            sessions: records.len(),
+            locked_secs: days.values().sum(),
+            longest: records
+                .iter()
+                .max_by_key(|r| r.duration_secs)
+                .map(|r| (*r).clone()),
+            unlocks,
+            failed_attempts,
+            failure_rate: if attempts == 0 {
+                0.0
+            } else {
+                f64::from(failed_attempts) / attempts as f64
+            },
+            per_day: days
+                .into_iter()
+                .map(|(day, locked_secs)| PeriodTotal {
+                    period: day.format("%Y-%m-%d").to_string(),
+                    locked_secs,
+                })
+                .collect(),
+            per_week: weeks
+                .into_iter()
+                .map(|((year, week), locked_secs)| PeriodTotal {
+                    period: format!("{}-W{:02}", year, week),
+                    locked_secs,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Since {}:", local_time(self.since));
+        println!("  Sessions: {}", self.sessions);
+        println!("  Locked: {}", format_duration(self.locked_secs));
+        if let Some(longest) = &self.longest {
+            println!(
+                "  Longest: {} (from {})",
+                format_duration(longest.duration_secs),
+                local_time(longest.started)
+            );
+        }
+        println!(
+            "  Failed attempts: {} of {} ({:.1}%)",
+            self.failed_attempts,
+            self.unlocks as u64 + u64::from(self.failed_attempts),
+            self.failure_rate * 100.0
+        );
+        for (title, totals) in [("Day", &self.per_day), ("Week", &self.per_week)] {
+            if totals.is_empty() {
+                continue;
+            }
+            println!("\n{:<10}  {:>8}", title, "Locked");
+            for total in totals {
+                println!(
+                    "{:<10}  {:>8}",
+                    total.period,
+                    format_duration(total.locked_secs)
+                );
+            }
+        }
+    }
+}
+
+/// Split the time from `start` to `end` (unix seconds) at each midnight in
+/// `tz`.
+fn split_by_day<Tz: TimeZone>(start: u64, end: u64, tz: &Tz) -> Vec<(NaiveDate, u64)> {
+    let mut parts = Vec::new();
+    let mut at = start;
+    while at < end {
+        let Some(local) = tz.timestamp_opt(at as i64, 0).earliest() else {
+            break;
+        };
+        let day = local.date_naive();
+        let next_midnight = day
+            .succ_opt()
+            .and_then(|next| {
+                tz.from_local_datetime(&next.and_time(NaiveTime::MIN))
+                    .earliest()
+            })
+            .map_or(end, |midnight| midnight.timestamp() as u64);
+        let until = next_midnight.clamp(at + 1, end);
+        parts.push((day, until - at));
+        at = until;
+    }
+    parts
+}
+
+fn local_time(secs: u64) -> String {
+    Local.timestamp_opt(secs as i64, 0).earliest().map_or_else(
+        || secs.to_string(),
+        |t| t.format("%Y-%m-%d %H:%M").to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit;
+    use chrono::Utc;
+    use std::fs;
+
+    #[test]
+    fn test_sessions_and_stats() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        fs::remove_file(&path).ok();
+
+        // 2026-10-12 22:00 UTC, a Monday: locked for four hours over midnight
+        // with two failed attempts, then a lock that never got unlocked.
+        let start = 1_791_842_400;
+        audit::append(&path, AuditEvent::LockStarted, start).unwrap();
+        audit::append(&path, AuditEvent::UnlockFailed, start + 60).unwrap();
+        audit::append(&path, AuditEvent::UnlockFailed, start + 120).unwrap();
+        let unlocked = AuditEvent::Unlocked {
+            method: Credential::Primary,
+            duration_secs: 4 * 3600,
+        };
+        audit::append(&path, unlocked, start + 4 * 3600).unwrap();
+        audit::append(&path, AuditEvent::LockStarted, start + 5 * 3600).unwrap();
+        audit::append(&path, AuditEvent::UnlockFailed, start + 5 * 3600 + 600).unwrap();
+
+        let entries = audit::read(&path).unwrap();
+        let now = start + 6 * 3600;
+        let records = sessions(&entries, now, |_, _| false);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].ending, Ending::Unlocked);
+        assert_eq!(records[0].method, Some(Credential::Primary));
+        assert_eq!(records[0].failed_attempts, 2);
+        assert_eq!(records[1].ending, Ending::Interrupted);
+        assert_eq!(records[1].duration_secs, 600);
+        // Entries are all written by this process. It only counts as still
+        // locked with a lock session record from after the lock started.
+        let pid = std::process::id();
+        let record = |kind, started| SessionRecord {
+            kind,
+            pid,
+            started,
+            sleep_profile: "system".to_string(),
+            sleep_state: "active".to_string(),
+            sleep_detail: None,
+        };
+        for stale in [
+            Vec::new(),
+            vec![record(SessionKind::Awake, start + 5 * 3600)],
+            vec![record(SessionKind::Lock, start + 5 * 3600 - 1)],
+        ] {
+            let records = sessions(&entries, now, |pid, started| {
+                lock_running(&stale, pid, started)
+            });
+            assert_eq!(records[1].ending, Ending::Interrupted);
+        }
+        let live = vec![record(SessionKind::Lock, start + 5 * 3600)];
+        let running = sessions(&entries, now, |pid, started| {
+            lock_running(&live, pid, started)
+        });
+        assert_eq!(running[1].ending, Ending::Running);
+        assert_eq!(running[1].duration_secs, 3600);
+
+        let stats = Stats::compute(&records, start - 3600, &Utc);
+        assert_eq!(stats.sessions, 2);
+        assert_eq!(stats.locked_secs, 4 * 3600 + 600);
+        assert_eq!(stats.longest.as_ref().unwrap().started, start);
+        assert_eq!(stats.failed_attempts, 3);
+        assert_eq!(stats.failure_rate, 0.75);
+        assert_eq!(
+            stats.per_day,
+            vec![
+                PeriodTotal {
+                    period: "2026-10-12".to_string(),
+                    locked_secs: 2 * 3600,
+                },
+                PeriodTotal {
+                    period: "2026-10-13".to_string(),
+                    locked_secs: 2 * 3600 + 600,
+                },
+            ]
+        );
+        assert_eq!(stats.per_week.len(), 1);
+        assert_eq!(stats.per_week[0].period, "2026-W42");
+
+        // Only the part after `since` counts.
+        let recent = Stats::compute(&records, start + 3 * 3600, &Utc);
+        assert_eq!(recent.locked_secs, 3600 + 600);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{bail, Context, Result};
+use global_hotkey::hotkey::{HotKey, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Hotkeys tried when neither the config nor the command line names any.
+pub const DEFAULT_HOTKEYS: [&str; 2] = ["super+shift+l", "super+alt+l"];
+
+/// Daemon settings, read from `daemon.json` next to `auth.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Lock hotkeys in order of preference: the first one that registers
+    /// is used, the rest are fallbacks.
+    pub hotkeys: Vec<String>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            hotkeys: DEFAULT_HOTKEYS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Load the config at `path`, treating a missing file as the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path).context("Failed to read daemon config")?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Invalid daemon config {}", path.display()))
+    }
+}
+
+pub fn get_daemon_config_path() -> Result<std::path::PathBuf> {
+    Ok(crate::auth::get_config_path()?.with_file_name("daemon.json"))
+}
+
+/// Parse a hotkey such as `ctrl+alt+l` or `super+shift+F12`. Modifiers come
+/// first; `cmd`, `win` and `meta` are accepted for super and `option` for
+/// alt. The combination needs ctrl, alt or super, since a bare key or
+/// shift+key would fire while typing.
+pub fn parse_hotkey(text: &str) -> Result<HotKey> {
+    let normalized = text
+        .split('+')
+        .map(|token| match token.trim().to_lowercase().as_str() {
+            "win" | "windows" | "meta" | "logo" => "super".to_string(),
+            _ => token.trim().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("+");
+
+    let hotkey = HotKey::from_str(&normalized)
+        .map_err(|e| anyhow::anyhow!("Invalid hotkey '{}': {}", text, e))?;
+
+    let required = Modifiers::CONTROL | Modifiers::ALT | Modifiers::SUPER;
+    if !hotkey.mods.intersects(required) {
+        bail!(
+            "Invalid hotkey '{}': add ctrl, alt or super so it cannot fire while typing",
+            text
+        );
+    }
+    Ok(hotkey)
+}
+
+/// Validate a `--hotkey` argument while keeping its original spelling.
+pub fn parse_hotkey_arg(text: &str) -> Result<String> {
+    parse_hotkey(text)?;
+    Ok(text.to_string())
+}
+
+/// Parse every hotkey in `texts`, keeping their order.
+pub fn parse_hotkeys(texts: &[String]) -> Result<Vec<(String, HotKey)>> {
+    if texts.is_empty() {
+        bail!("No hotkeys configured");
+    }
+    texts
+        .iter()
+        .map(|text| Ok((text.clone(), parse_hotkey(text)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use global_hotkey::hotkey::Code;
+
+    #[test]
+    fn test_parse_hotkey() {
+        assert_eq!(
+            parse_hotkey("ctrl+alt+l").unwrap(),
+            HotKey::new(Some(Modifiers::CONTROL | Modifiers::ALT), Code::KeyL)
+        );
+        assert_eq!(
+            parse_hotkey("Cmd + Shift + L").unwrap(),
+            HotKey::new(Some(Modifiers::SUPER | Modifiers::SHIFT), Code::KeyL)
+        );
+        assert_eq!(
+            parse_hotkey("win+F12").unwrap(),
+            HotKey::new(Some(Modifiers::SUPER), Code::F12)
+        );
+    }
+
+    #[test]
+    fn test_parse_hotkey_rejects_bad_combinations() {
+        for bad in [
+            "l",
+            "shift+l",
+            "ctrl+alt",
+            "ctrl+l+k",
+            "ctrl++l",
+            "ctrl+nope",
+        ] {
+            let err = parse_hotkey(bad).unwrap_err().to_string();
+            assert!(err.contains(bad), "{}: {}", bad, err);
+        }
+        assert!(parse_hotkeys(&[]).is_err());
+    }
+
+    #[test]
+    fn test_daemon_config_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("agent-lock-daemon-{}.json", std::process::id()));
+        assert_eq!(DaemonConfig::load(&path).unwrap(), DaemonConfig::default());
+
+        fs::write(&path, r#"{"hotkeys": ["ctrl+alt+l"]}"#).unwrap();
+        assert_eq!(
+            DaemonConfig::load(&path).unwrap().hotkeys,
+            vec!["ctrl+alt+l"]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::Result;
+use std::time::Duration;
+
+/// Reports how long the user has been away from the keyboard and mouse.
+pub trait IdleSource {
+    fn idle_time(&mut self) -> Result<Duration>;
+}
+
+/// Decides when the daemon should lock because the user went idle. It fires
+/// once per idle period: after a lock, the user has to come back (idle time
+/// drops below the threshold) before it can fire again.
+pub struct IdleLock {
+    source: Box<dyn IdleSource>,
+    threshold: Duration,
+    fired: bool,
+}
+
+impl IdleLock {
+    pub fn new(source: Box<dyn IdleSource>, threshold: Duration) -> Self {
+        Self {
+            source,
+            threshold,
+            fired: false,
+        }
+    }
+
+    /// Whether to lock now. An idle period that passes the threshold while a
+    /// lock is already active counts as handled.
+    pub fn should_lock(&mut self, lock_active: bool) -> Result<bool> {
+        let idle = self.source.idle_time()?;
+        if idle < self.threshold {
+            self.fired = false;
+            return Ok(false);
+        }
+        if self.fired {
+            return Ok(false);
+        }
+        self.fired = true;
+        Ok(!lock_active)
+    }
+}
+
+/// The idle source for the current platform and session.
+#[cfg(target_os = "linux")]
+pub fn default_source() -> Result<Box<dyn IdleSource>> {
+    // Under Wayland the X screensaver extension only sees XWayland clients,
+    // so ask logind there.
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_some() {
+        match X11Idle::connect() {
+            Ok(source) => return Ok(Box::new(source)),
+            Err(e) => eprintln!(
+                "X11 idle time unavailable ({:#}), falling back to logind",
+                e
+            ),
+        }
+    }
+    Ok(Box::new(LogindIdle::connect()?))
+}
+
+#[cfg(target_os = "macos")]
+pub fn default_source() -> Result<Box<dyn IdleSource>> {
+    Ok(Box::new(MacIdle))
+}
+
+#[cfg(target_os = "windows")]
+pub fn default_source() -> Result<Box<dyn IdleSource>> {
+    Ok(Box::new(WindowsIdle))
+}
+
+/// Time since the last input event, from the X11 MIT-SCREEN-SAVER extension.
+#[cfg(target_os = "linux")]
+pub struct X11Idle {
+    conn: x11rb::rust_connection::RustConnection,
+    root: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl X11Idle {
+    pub fn connect() -> Result<Self> {
+        use anyhow::Context;
+        use x11rb::connection::Connection;
+        use x11rb::protocol::screensaver::ConnectionExt;
+
+        let (conn, screen) = x11rb::connect(None).context("Failed to connect to X server")?;
+        conn.screensaver_query_version(1, 1)?
+            .reply()
+            .context("X server lacks the MIT-SCREEN-SAVER extension")?;
+        let root = conn.setup().roots[screen].root;
+        Ok(Self { conn, root })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IdleSource for X11Idle {
+    fn idle_time(&mut self) -> Result<Duration> {
+        use x11rb::protocol::screensaver::ConnectionExt;
+
+        let info = self.conn.screensaver_query_info(self.root)?.reply()?;
+        Ok(Duration::from_millis(info.ms_since_user_input.into()))
+    }
+}
+
+/// Idle time from the `IdleHint` of the caller's logind session, which
+/// desktops and idle daemons set once the user stops interacting.
+#[cfg(target_os = "linux")]
+pub struct LogindIdle {
+    session: zbus::blocking::Proxy<'static>,
+}
+
+#[cfg(target_os = "linux")]
+impl LogindIdle {
+    pub fn connect() -> Result<Self> {
+        use anyhow::Context;
+
+        let conn =
+            zbus::blocking::Connection::system().context("Failed to connect to the system bus")?;
+        let session = zbus::blocking::Proxy::new(
+            &conn,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1/session/auto",
+            "org.freedesktop.login1.Session",
+        )
+        .context("Failed to reach the logind session")?;
+        Ok(Self { session })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IdleSource for LogindIdle {
+    fn idle_time(&mut self) -> Result<Duration> {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let idle: bool = self.session.get_property("IdleHint")?;
+        if !idle {
+            return Ok(Duration::ZERO);
+        }
+        let since_usec: u64 = self.session.get_property("IdleSinceHint")?;
+        let since = UNIX_EPOCH + Duration::from_micros(since_usec);
+        Ok(SystemTime::now().duration_since(since).unwrap_or_default())
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub struct MacIdle;
+
+#[cfg(target_os = "macos")]
+impl IdleSource for MacIdle {
+    fn idle_time(&mut self) -> Result<Duration> {
+        #[link(name = "CoreGraphics", kind = "framework")]
+        extern "C" {
+            fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+        }
+        const COMBINED_SESSION_STATE: i32 = 0;
+        const ANY_INPUT_EVENT: u32 = !0;
+
+        let secs = unsafe {
+            CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT)
+        };
+        Ok(Duration::from_secs_f64(secs.max(0.0)))
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub struct WindowsIdle;
+
+#[cfg(target_os = "windows")]
+impl IdleSource for WindowsIdle {
+    fn idle_time(&mut self) -> Result<Duration> {
+        use windows::Win32::System::SystemInformation::GetTickCount;
+        use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+        let mut info = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+            anyhow::bail!("GetLastInputInfo failed");
+        }
+        let now = unsafe { GetTickCount() };
+        Ok(Duration::from_millis(now.wrapping_sub(info.dwTime).into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FakeIdle(Rc<RefCell<Duration>>);
+
+    impl IdleSource for FakeIdle {
+        fn idle_time(&mut self) -> Result<Duration> {
+            Ok(*self.0.borrow())
+        }
+    }
+
+    #[test]
+    fn test_idle_lock_fires_once_per_idle_period() {
+        let idle = Rc::new(RefCell::new(Duration::from_secs(30)));
+        let mut watcher = IdleLock::new(
+            Box::new(FakeIdle(Rc::clone(&idle))),
+            Duration::from_secs(600),
+        );
+
+        assert!(!watcher.should_lock(false).unwrap());
+
+        *idle.borrow_mut() = Duration::from_secs(600);
+        assert!(watcher.should_lock(false).unwrap());
+        // Still idle while the lock is up: no second lock.
+        *idle.borrow_mut() = Duration::from_secs(900);
+        assert!(!watcher.should_lock(true).unwrap());
+        assert!(!watcher.should_lock(false).unwrap());
+
+        // The user came back, then left again.
+        *idle.borrow_mut() = Duration::from_secs(1);
+        assert!(!watcher.should_lock(false).unwrap());
+        *idle.borrow_mut() = Duration::from_secs(601);
+        assert!(watcher.should_lock(false).unwrap());
+    }
+
+    #[test]
+    fn test_idle_lock_skips_while_locked() {
+        let idle = Rc::new(RefCell::new(Duration::from_secs(700)));
+        let mut watcher = IdleLock::new(
+            Box::new(FakeIdle(Rc::clone(&idle))),
+            Duration::from_secs(600),
+        );
+
+        // Locked by hotkey before the threshold passed; unlocking later must
+        // not trigger an immediate idle lock for the same idle period.
+        assert!(!watcher.should_lock(true).unwrap());
+        assert!(!watcher.should_lock(false).unwrap());
+    }
+}
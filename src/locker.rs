@@ -1,234 +1,280 @@
+use crate::auth::{AuthConfig, CredentialKind, TotpMode};
+use crate::duration::format_duration;
 use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+#[cfg(test)]
+pub mod headless;
+#[cfg(any(unix, target_os = "windows"))]
+mod keys;
 #[cfg(target_os = "macos")]
-pub fn show_lock_screen() -> Result<()> {
-    use cocoa::appkit::{
-        NSApp, NSApplication, NSApplicationActivationPolicy, NSBackingStoreBuffered, NSColor,
-        NSScreen, NSTextField, NSWindow, NSWindowStyleMask,
-    };
-    use cocoa::base::{id, nil, NO, YES};
-    use cocoa::foundation::{NSAutoreleasePool, NSPoint, NSRect, NSSize, NSString};
-    use objc::declare::ClassDecl;
-    use objc::runtime::{Class, Object, Sel};
-    use objc::{class, msg_send, sel, sel_impl};
-    use std::sync::{Arc, Mutex};
-
-    unsafe {
-        let _pool = NSAutoreleasePool::new(nil);
-
-        let app = NSApp();
-        if app == nil {
-            let app = NSApplication::sharedApplication(nil);
-            app.setActivationPolicy_(NSApplicationActivationPolicy::NSApplicationActivationPolicyRegular);
-        }
+mod macos;
+#[cfg(unix)]
+mod tty;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "linux")]
+mod x11;
+
+/// How often a waiting prompt checks its [`InfoPanel`] for new lines.
+pub const PANEL_REFRESH: Duration = Duration::from_millis(500);
+
+/// Status lines shown under the prompt, such as the state of a command
+/// started by `agent-lock run`. Other threads update it while the screen is
+/// locked; the backend picks up changes within [`PANEL_REFRESH`]. The panel
+/// is display only and never takes input.
+///
+/// Lines are grouped in named sections, shown in the order they were first
+/// set, so independent sources can update their part without coordinating.
+#[derive(Clone, Default)]
+pub struct InfoPanel {
+    inner: Arc<Mutex<PanelLines>>,
+}
 
-        let app = NSApp();
-        let screen = NSScreen::mainScreen(nil);
-        let screen_frame: NSRect = msg_send![screen, frame];
-
-        let window_class = create_window_class();
-        let style_mask = NSWindowStyleMask::NSBorderlessWindowMask;
-
-        let window: id = msg_send![window_class, alloc];
-        let window: id = msg_send![
-            window,
-            initWithContentRect: screen_frame
-            styleMask: style_mask
-            backing: NSBackingStoreBuffered
-            defer: NO
-        ];
-
-        let _: () = msg_send![window, setLevel: 1000i32];
-        let black_color: id = msg_send![class!(NSColor), blackColor];
-        let _: () = msg_send![window, setBackgroundColor: black_color];
-        let _: () = msg_send![window, setCollectionBehavior: 1 << 10];
-        let _: () = msg_send![window, setAcceptsMouseMovedEvents: YES];
-        let _: () = msg_send![window, makeKeyAndOrderFront: nil];
-        let _: () = msg_send![window, orderFrontRegardless];
-
-        let content_view: id = msg_send![window, contentView];
-
-        let label: id = NSTextField::alloc(nil);
-        let label_frame = NSRect::new(
-            NSPoint::new(screen_frame.size.width / 2.0 - 150.0, screen_frame.size.height / 2.0 + 50.0),
-            NSSize::new(300.0, 40.0),
-        );
-        let _: id = msg_send![label, initWithFrame: label_frame];
-        let text = NSString::alloc(nil).init_str("Screen Locked");
-        let _: () = msg_send![label, setStringValue: text];
-        let _: () = msg_send![label, setBezeled: NO];
-        let _: () = msg_send![label, setDrawsBackground: NO];
-        let _: () = msg_send![label, setEditable: NO];
-        let _: () = msg_send![label, setSelectable: NO];
-        let white_color: id = msg_send![class!(NSColor), whiteColor];
-        let _: () = msg_send![label, setTextColor: white_color];
-        let _: () = msg_send![label, setAlignment: 1i64];
-        let font: id = msg_send![class!(NSFont), systemFontOfSize: 28.0];
-        let _: () = msg_send![label, setFont: font];
-        let _: () = msg_send![content_view, addSubview: label];
-
-        let secure_field: id = msg_send![class!(NSTextField), alloc];
-        let field_frame = NSRect::new(
-            NSPoint::new(screen_frame.size.width / 2.0 - 150.0, screen_frame.size.height / 2.0),
-            NSSize::new(300.0, 35.0),
-        );
-        let _: id = msg_send![secure_field, initWithFrame: field_frame];
-        let placeholder = NSString::alloc(nil).init_str("Enter PIN (numbers only)");
-        let _: () = msg_send![secure_field, setPlaceholderString: placeholder];
-        let _: () = msg_send![secure_field, setBezeled: YES];
-        let _: () = msg_send![secure_field, setBezelStyle: 1i64];
-        let _: () = msg_send![secure_field, setDrawsBackground: YES];
-        let _: () = msg_send![secure_field, setEditable: YES];
-        let _: () = msg_send![secure_field, setSelectable: YES];
-
-        let bg_color: id = msg_send![class!(NSColor), whiteColor];
-        let _: () = msg_send![secure_field, setBackgroundColor: bg_color];
-        let text_color: id = msg_send![class!(NSColor), blackColor];
-        let _: () = msg_send![secure_field, setTextColor: text_color];
-
-        let _: () = msg_send![content_view, addSubview: secure_field];
-        let _: () = msg_send![secure_field, becomeFirstResponder];
-
-        let button: id = msg_send![class!(NSButton), alloc];
-        let button_frame = NSRect::new(
-            NSPoint::new(screen_frame.size.width / 2.0 - 60.0, screen_frame.size.height / 2.0 - 50.0),
-            NSSize::new(120.0, 32.0),
-        );
-        let _: id = msg_send![button, initWithFrame: button_frame];
-        let button_text = NSString::alloc(nil).init_str("Unlock");
-        let _: () = msg_send![button, setTitle: button_text];
-        let _: () = msg_send![button, setBezelStyle: 1i64];
-        let _: () = msg_send![content_view, addSubview: button];
+#[derive(Default)]
+struct PanelLines {
+    sections: Vec<(String, Vec<String>)>,
+    version: u64,
+}
 
-        let state = Arc::new(Mutex::new(UnlockState {
-            window,
-            secure_field,
-            app,
-        }));
+impl InfoPanel {
+    /// Replace the lines of section `name`.
+    pub fn set_section(&self, name: &str, lines: Vec<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner
+            .sections
+            .iter_mut()
+            .find(|(section, _)| section == name)
+        {
+            Some((_, current)) if *current == lines => return,
+            Some((_, current)) => *current = lines,
+            None => inner.sections.push((name.to_string(), lines)),
+        }
+        inner.version += 1;
+    }
 
-        let target = create_target(state);
-        let _: () = msg_send![button, setTarget: target];
-        let _: () = msg_send![button, setAction: sel!(handleUnlock:)];
-        let _: () = msg_send![button, setKeyEquivalent: NSString::alloc(nil).init_str("\r")];
+    pub fn lines(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .sections
+            .iter()
+            .flat_map(|(_, lines)| lines.iter().cloned())
+            .collect()
+    }
 
-        let _: () = msg_send![app, activateIgnoringOtherApps: YES];
-        let _: () = msg_send![window, makeKeyAndOrderFront: nil];
-        let _: () = msg_send![window, makeFirstResponder: secure_field];
+    /// Whether the lines changed since the version in `seen`, which is
+    /// updated to the current one.
+    pub fn changed(&self, seen: &mut u64) -> bool {
+        let version = self.inner.lock().unwrap().version;
+        std::mem::replace(seen, version) != version
+    }
+}
 
-        // Force window to become key
-        let _: () = msg_send![window, becomeKeyWindow];
-        let _: () = msg_send![window, becomeMainWindow];
-        let _: () = msg_send![secure_field, selectText: nil];
+/// Longest run of `*` drawn for the typed input, so a long passphrase does
+/// not run off the prompt.
+const MAX_MASK_LEN: usize = 32;
 
-        app.run();
+/// What the lock screen asks for: the kind of secret and which
+/// authenticator code, if any.
+fn unlock_method() -> (CredentialKind, Option<TotpMode>) {
+    match crate::auth::get_config_path().and_then(|path| AuthConfig::load(&path)) {
+        Ok(config) => (config.credential.kind, config.totp.map(|totp| totp.mode)),
+        Err(_) => (CredentialKind::Pin, None),
     }
+}
 
-    Ok(())
+/// What an empty prompt asks for.
+pub fn prompt_hint() -> String {
+    let (kind, totp) = unlock_method();
+    match totp {
+        None => format!("Enter {}", kind.name()),
+        Some(TotpMode::PinAndCode) => format!("Enter {} + 6-digit code", kind.name()),
+        Some(TotpMode::CodeOnly) => "Enter 6-digit code".to_string(),
+    }
 }
 
-#[cfg(target_os = "macos")]
-struct UnlockState {
-    window: cocoa::base::id,
-    secure_field: cocoa::base::id,
-    app: cocoa::base::id,
+/// The typed input as it is drawn: one `*` per character, up to a limit.
+pub fn masked(input: &str) -> String {
+    "*".repeat(input.chars().count().min(MAX_MASK_LEN))
 }
 
-#[cfg(target_os = "macos")]
-unsafe fn create_window_class() -> *const objc::runtime::Class {
-    use objc::declare::ClassDecl;
-    use objc::runtime::{Class, Object, Sel, BOOL};
-    use objc::{class, msg_send, sel, sel_impl};
+/// Feedback shown after a rejected PIN, including any lockout wait.
+pub fn failure_message() -> String {
+    if let Some(wait) = crate::auth::lockout_remaining() {
+        return format!(
+            "Too many attempts - try again in {}",
+            format_duration(wait.as_secs())
+        );
+    }
+    let (kind, totp) = unlock_method();
+    match totp {
+        None => format!("Incorrect {}", kind.name()),
+        Some(TotpMode::PinAndCode) => format!("Incorrect {} or code", kind.name()),
+        Some(TotpMode::CodeOnly) => "Incorrect code".to_string(),
+    }
+}
 
-    let superclass = class!(NSWindow);
-    let mut decl = ClassDecl::new("LockWindow", superclass).unwrap();
+/// A lock screen implementation. [`run`] drives it through one session:
+/// `show`, then `prompt` until a PIN verifies, then `teardown`.
+pub trait LockBackend {
+    /// Cover every screen and take over input.
+    fn show(&mut self) -> Result<()>;
 
-    extern "C" fn can_become_key(_this: &Object, _cmd: Sel) -> BOOL {
-        cocoa::base::YES
-    }
+    /// Block until the user submits a PIN.
+    fn prompt(&mut self) -> Result<String>;
 
-    extern "C" fn can_become_main(_this: &Object, _cmd: Sel) -> BOOL {
-        cocoa::base::YES
-    }
+    /// The submitted PIN was rejected; `message` says why.
+    fn unlock_failed(&mut self, message: &str) -> Result<()>;
 
-    decl.add_method(
-        sel!(canBecomeKeyWindow),
-        can_become_key as extern "C" fn(&Object, Sel) -> BOOL,
-    );
+    /// The submitted PIN was accepted.
+    fn unlock_succeeded(&mut self) -> Result<()>;
 
-    decl.add_method(
-        sel!(canBecomeMainWindow),
-        can_become_main as extern "C" fn(&Object, Sel) -> BOOL,
-    );
+    /// Release input and remove the overlay.
+    fn teardown(&mut self) -> Result<()>;
 
-    decl.register()
+    /// Show `panel` under the prompt and keep it up to date while locked.
+    fn set_panel(&mut self, panel: InfoPanel);
 }
 
-#[cfg(target_os = "macos")]
-unsafe fn create_target(state: std::sync::Arc<std::sync::Mutex<UnlockState>>) -> cocoa::base::id {
-    use cocoa::foundation::NSString;
-    use objc::declare::ClassDecl;
-    use objc::runtime::{Object, Sel};
-    use objc::{class, msg_send, sel, sel_impl};
-    use std::sync::{Arc, Mutex};
-
-    let superclass = class!(NSObject);
-    let mut decl = ClassDecl::new("UnlockTarget", superclass).unwrap();
-
-    extern "C" fn handle_unlock(this: &Object, _cmd: Sel, _sender: cocoa::base::id) {
-        unsafe {
-            use cocoa::foundation::NSString;
-            use objc::{class, msg_send, sel, sel_impl};
-            use std::sync::{Arc, Mutex};
-
-            let state_ptr: *mut std::ffi::c_void = *this.get_ivar("state");
-            let state = Arc::from_raw(state_ptr as *const Mutex<UnlockState>);
-
-            let pin = {
-                let locked_state = state.lock().unwrap();
-                let string_value: cocoa::base::id = msg_send![locked_state.secure_field, stringValue];
-                let utf8_ptr: *const i8 = msg_send![string_value, UTF8String];
-                std::ffi::CStr::from_ptr(utf8_ptr).to_string_lossy().to_string()
-            };
-
-            if crate::auth::verify_pin_internal(&pin) {
-                let locked_state = state.lock().unwrap();
-                let _: () = msg_send![locked_state.app, stop: cocoa::base::nil];
-                let _: () = msg_send![locked_state.window, close];
+/// Run one lock session on `backend` until `verify` accepts a PIN.
+///
+/// Only an accepted PIN tears the backend down. If the lock fails once it
+/// is up, the error is returned with the lock still held, so a broken
+/// connection or keyboard never unlocks the screen. A `show` that fails
+/// partway is torn down, since nothing was locked yet.
+pub fn run(backend: &mut dyn LockBackend, mut verify: impl FnMut(&str) -> bool) -> Result<()> {
+    if let Err(e) = backend.show() {
+        let _ = backend.teardown();
+        return Err(e);
+    }
+
+    loop {
+        let pin = backend.prompt()?;
+        if verify(&pin) {
+            let result = backend.unlock_succeeded();
+            return result.and(backend.teardown());
+        }
+        backend.unlock_failed(&failure_message())?;
+    }
+}
+
+/// Wait up to `timeout` for `fd` to become readable. Returns false on
+/// timeout or when a signal interrupts the wait.
+#[cfg(unix)]
+fn wait_readable(fd: std::os::fd::RawFd, timeout: Duration) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+        -1 => {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                Ok(false)
             } else {
-                let locked_state = state.lock().unwrap();
-                let empty = NSString::alloc(cocoa::base::nil).init_str("");
-                let _: () = msg_send![locked_state.secure_field, setStringValue: empty];
+                Err(error.into())
             }
-
-            std::mem::forget(state);
         }
+        0 => Ok(false),
+        _ => Ok(true),
     }
+}
 
-    decl.add_ivar::<*mut std::ffi::c_void>("state");
-    decl.add_method(
-        sel!(handleUnlock:),
-        handle_unlock as extern "C" fn(&Object, Sel, cocoa::base::id),
-    );
+/// A lock prompt on the controlling terminal instead of a GUI overlay.
+#[cfg(unix)]
+pub fn tty_backend() -> Result<Box<dyn LockBackend>> {
+    Ok(Box::new(tty::TtyBackend::open()?))
+}
 
-    let target_class = decl.register();
-    let target: cocoa::base::id = msg_send![target_class, alloc];
-    let target: cocoa::base::id = msg_send![target, init];
+#[cfg(not(unix))]
+pub fn tty_backend() -> Result<Box<dyn LockBackend>> {
+    anyhow::bail!("Terminal lock is only supported on Unix");
+}
 
-    let state_ptr = Arc::into_raw(state) as *mut std::ffi::c_void;
-    (*target).set_ivar("state", state_ptr);
+/// The overlay for the current platform and session.
+#[cfg(target_os = "macos")]
+pub fn native_backend() -> Result<Box<dyn LockBackend>> {
+    Ok(Box::new(macos::MacBackend::new()))
+}
 
-    target
+#[cfg(target_os = "linux")]
+pub fn native_backend() -> Result<Box<dyn LockBackend>> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        return Ok(Box::new(wayland::WaylandLocker::connect()?));
+    }
+    if std::env::var_os("DISPLAY").is_none() {
+        anyhow::bail!(
+            "No graphical session found. Use 'agent-lock lock --tty' to lock this terminal"
+        );
+    }
+    Ok(Box::new(x11::X11Locker::connect(None)?))
 }
 
 #[cfg(target_os = "windows")]
-pub fn show_lock_screen() -> Result<()> {
-    anyhow::bail!("Windows implementation coming soon");
+pub fn native_backend() -> Result<Box<dyn LockBackend>> {
+    Ok(Box::new(windows::WindowsLocker::new()))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    use headless::{HeadlessBackend, HeadlessEvent};
+
+    #[test]
+    fn test_run_retries_until_verified() {
+        let mut backend = HeadlessBackend::new(["0000", "1234"]);
+        run(&mut backend, |pin| pin == "1234").unwrap();
+
+        let events = backend.events();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0], HeadlessEvent::Shown);
+        assert_eq!(events[1], HeadlessEvent::Prompted("0000".to_string()));
+        assert!(matches!(events[2], HeadlessEvent::Failed(_)));
+        assert_eq!(events[3], HeadlessEvent::Prompted("1234".to_string()));
+        assert_eq!(events[4], HeadlessEvent::Succeeded);
+        assert_eq!(events[5], HeadlessEvent::TornDown);
+    }
+
+    #[test]
+    fn test_run_stays_locked_on_error() {
+        // The script runs out after one wrong PIN, so prompt fails.
+        let mut backend = HeadlessBackend::new(["0000"]);
+        assert!(run(&mut backend, |_| false).is_err());
+        assert!(!backend.events().contains(&HeadlessEvent::TornDown));
+    }
+
+    #[test]
+    fn test_info_panel_changes() {
+        let panel = InfoPanel::default();
+        let mut backend = HeadlessBackend::new(["1234"]);
+        backend.set_panel(panel.clone());
+        let mut seen = 0;
+        assert!(!panel.changed(&mut seen));
+
+        panel.set_section("run", vec!["agent running".to_string()]);
+        assert!(panel.changed(&mut seen));
+        assert!(!panel.changed(&mut seen));
+        assert_eq!(backend.panel_lines(), vec!["agent running"]);
+
+        panel.set_section("run", vec!["agent running".to_string()]);
+        assert!(!panel.changed(&mut seen));
+
+        panel.set_section("status", vec!["cpu 3%".to_string()]);
+        panel.set_section("run", vec!["agent exited".to_string()]);
+        assert!(panel.changed(&mut seen));
+        assert_eq!(panel.lines(), vec!["agent exited", "cpu 3%"]);
+    }
+
     #[test]
-    fn test_compiles() {
-        assert!(true);
+    fn test_masked_input_is_capped() {
+        assert_eq!(masked("1234"), "****");
+        assert_eq!(masked("pässphrase"), "**********");
+        assert_eq!(masked(&"x".repeat(90)).len(), MAX_MASK_LEN);
     }
 }
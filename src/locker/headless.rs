@@ -0,0 +1,78 @@
+use super::{InfoPanel, LockBackend};
+use anyhow::Result;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadlessEvent {
+    Shown,
+    Prompted(String),
+    Failed(String),
+    Succeeded,
+    TornDown,
+}
+
+/// Backend with no UI that replays a script of PINs and records every call
+/// the lock flow makes, so sessions can be tested without a display.
+pub struct HeadlessBackend {
+    pins: VecDeque<String>,
+    events: Vec<HeadlessEvent>,
+    panel: InfoPanel,
+}
+
+impl HeadlessBackend {
+    pub fn new<I, S>(pins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            pins: pins.into_iter().map(Into::into).collect(),
+            events: Vec::new(),
+            panel: InfoPanel::default(),
+        }
+    }
+
+    pub fn events(&self) -> &[HeadlessEvent] {
+        &self.events
+    }
+
+    /// The panel lines as they would be drawn right now.
+    pub fn panel_lines(&self) -> Vec<String> {
+        self.panel.lines()
+    }
+}
+
+impl LockBackend for HeadlessBackend {
+    fn show(&mut self) -> Result<()> {
+        self.events.push(HeadlessEvent::Shown);
+        Ok(())
+    }
+
+    fn prompt(&mut self) -> Result<String> {
+        let pin = self
+            .pins
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("Headless script ran out of PINs"))?;
+        self.events.push(HeadlessEvent::Prompted(pin.clone()));
+        Ok(pin)
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        self.events.push(HeadlessEvent::Failed(message.to_string()));
+        Ok(())
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        self.events.push(HeadlessEvent::Succeeded);
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        self.events.push(HeadlessEvent::TornDown);
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        self.panel = panel;
+    }
+}
@@ -0,0 +1,112 @@
+//! Key handling shared by the native overlays. X11 and Wayland both report
+//! X keysyms, so one translation table covers them.
+
+pub const MAX_INPUT_LEN: usize = 128;
+
+#[cfg(target_os = "linux")]
+pub const XK_BACKSPACE: u32 = 0xff08;
+#[cfg(target_os = "linux")]
+pub const XK_RETURN: u32 = 0xff0d;
+#[cfg(target_os = "linux")]
+pub const XK_ESCAPE: u32 = 0xff1b;
+#[cfg(target_os = "linux")]
+pub const XK_KP_ENTER: u32 = 0xff8d;
+#[cfg(target_os = "linux")]
+pub const XK_KP_0: u32 = 0xffb0;
+#[cfg(target_os = "linux")]
+pub const XK_KP_9: u32 = 0xffb9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Escape,
+    Other,
+}
+
+#[cfg(target_os = "linux")]
+pub fn key_from_keysym(keysym: u32) -> Key {
+    match keysym {
+        XK_RETURN | XK_KP_ENTER => Key::Enter,
+        XK_BACKSPACE => Key::Backspace,
+        XK_ESCAPE => Key::Escape,
+        XK_KP_0..=XK_KP_9 => Key::Char(char::from(b'0' + (keysym - XK_KP_0) as u8)),
+        // Latin-1 keysyms share their values with the printable ASCII range.
+        0x20..=0x7e => Key::Char(char::from(keysym as u8)),
+        _ => Key::Other,
+    }
+}
+
+/// Apply a key press to the pending input. Returns the submitted input when
+/// the key was Enter and something had been typed.
+pub fn apply_key(input: &mut String, key: Key) -> Option<String> {
+    match key {
+        Key::Enter if !input.is_empty() => return Some(std::mem::take(input)),
+        Key::Char(c) if input.len() < MAX_INPUT_LEN => input.push(c),
+        Key::Backspace => {
+            input.pop();
+        }
+        Key::Escape => input.clear(),
+        _ => {}
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_key_from_keysym() {
+        assert_eq!(key_from_keysym('7' as u32), Key::Char('7'));
+        assert_eq!(key_from_keysym('a' as u32), Key::Char('a'));
+        assert_eq!(key_from_keysym(XK_KP_0 + 3), Key::Char('3'));
+        assert_eq!(key_from_keysym(XK_RETURN), Key::Enter);
+        assert_eq!(key_from_keysym(XK_KP_ENTER), Key::Enter);
+        assert_eq!(key_from_keysym(XK_BACKSPACE), Key::Backspace);
+        assert_eq!(key_from_keysym(0xffe1), Key::Other);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_valid_passphrase_can_be_typed() {
+        use crate::auth::{CharClass, CredentialPolicy};
+
+        let policy = CredentialPolicy {
+            required_classes: vec![
+                CharClass::Lower,
+                CharClass::Upper,
+                CharClass::Digit,
+                CharClass::Symbol,
+            ],
+            ..CredentialPolicy::passphrase()
+        };
+        let passphrase = "Correct horse 42 ~battery{staple}!";
+        policy.check(passphrase).unwrap();
+
+        // X11 and Wayland report printable ASCII as keysyms of the same value.
+        let mut input = String::new();
+        for c in passphrase.chars() {
+            assert_eq!(apply_key(&mut input, key_from_keysym(c as u32)), None);
+        }
+        assert_eq!(
+            apply_key(&mut input, key_from_keysym(XK_RETURN)),
+            Some(passphrase.to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_key() {
+        let mut input = String::new();
+        assert_eq!(apply_key(&mut input, Key::Enter), None);
+        for c in "125".chars() {
+            apply_key(&mut input, Key::Char(c));
+        }
+        apply_key(&mut input, Key::Backspace);
+        apply_key(&mut input, Key::Char('3'));
+        assert_eq!(apply_key(&mut input, Key::Enter), Some("123".to_string()));
+        assert!(input.is_empty());
+    }
+}
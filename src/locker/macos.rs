@@ -0,0 +1,484 @@
+use super::{InfoPanel, LockBackend, PANEL_REFRESH};
+use anyhow::Result;
+use cocoa::appkit::{
+    NSApp, NSApplication, NSApplicationActivationPolicy, NSBackingStoreBuffered, NSScreen,
+    NSTextField, NSWindowStyleMask,
+};
+use cocoa::base::{id, nil, NO, YES};
+use cocoa::foundation::{NSAutoreleasePool, NSPoint, NSRect, NSSize, NSString};
+use objc::declare::ClassDecl;
+use objc::runtime::{Object, Sel, BOOL};
+use objc::{class, msg_send, sel, sel_impl};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+/// Autoresizing mask that keeps a view centered when its window is resized.
+const CENTERED_AUTORESIZING: u64 = 1 | 4 | 8 | 32;
+const SCREEN_CHANGE_NOTIFICATION: &str = "NSApplicationDidChangeScreenParametersNotification";
+
+struct UnlockState {
+    app: id,
+    window: id,
+    secure_field: id,
+    blockers: Vec<id>,
+    submitted: Option<String>,
+    panel: InfoPanel,
+    panel_seen: u64,
+    panel_label: id,
+}
+
+/// Borderless windows above everything else: the primary screen gets the PIN
+/// field and Unlock button, every other screen a plain black window. The set
+/// of windows follows displays being plugged in or removed while locked.
+pub struct MacBackend {
+    app: id,
+    window: id,
+    secure_field: id,
+    observer: id,
+    target: id,
+    panel_timer: id,
+    /// Shared with the Objective-C target and observer, which hold a
+    /// reference each until `teardown`. Everything runs on the main thread.
+    state: Rc<RefCell<UnlockState>>,
+}
+
+impl MacBackend {
+    pub fn new() -> Self {
+        Self {
+            app: nil,
+            window: nil,
+            secure_field: nil,
+            observer: nil,
+            target: nil,
+            panel_timer: nil,
+            state: Rc::new(RefCell::new(UnlockState {
+                app: nil,
+                window: nil,
+                secure_field: nil,
+                blockers: Vec::new(),
+                submitted: None,
+                panel: InfoPanel::default(),
+                panel_seen: 0,
+                panel_label: nil,
+            })),
+        }
+    }
+}
+
+impl LockBackend for MacBackend {
+    fn show(&mut self) -> Result<()> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+
+            let app = NSApp();
+            if app == nil {
+                let app = NSApplication::sharedApplication(nil);
+                app.setActivationPolicy_(
+                    NSApplicationActivationPolicy::NSApplicationActivationPolicyRegular,
+                );
+            }
+
+            let app = NSApp();
+            let screen = primary_screen();
+            let screen_frame: NSRect = msg_send![screen, frame];
+
+            let window_class = create_window_class();
+            let style_mask = NSWindowStyleMask::NSBorderlessWindowMask;
+
+            let window: id = msg_send![window_class, alloc];
+            let window: id = msg_send![
+                window,
+                initWithContentRect: screen_frame
+                styleMask: style_mask
+                backing: NSBackingStoreBuffered
+                defer: NO
+            ];
+
+            let _: () = msg_send![window, setLevel: 1000i32];
+            let black_color: id = msg_send![class!(NSColor), blackColor];
+            let _: () = msg_send![window, setBackgroundColor: black_color];
+            let _: () = msg_send![window, setCollectionBehavior: 1 << 10];
+            let _: () = msg_send![window, setAcceptsMouseMovedEvents: YES];
+            let _: () = msg_send![window, makeKeyAndOrderFront: nil];
+            let _: () = msg_send![window, orderFrontRegardless];
+
+            let content_view: id = msg_send![window, contentView];
+
+            let label: id = NSTextField::alloc(nil);
+            let label_frame = NSRect::new(
+                NSPoint::new(
+                    screen_frame.size.width / 2.0 - 150.0,
+                    screen_frame.size.height / 2.0 + 50.0,
+                ),
+                NSSize::new(300.0, 40.0),
+            );
+            let _: id = msg_send![label, initWithFrame: label_frame];
+            let text = NSString::alloc(nil).init_str("Screen Locked");
+            let _: () = msg_send![label, setStringValue: text];
+            let _: () = msg_send![label, setBezeled: NO];
+            let _: () = msg_send![label, setDrawsBackground: NO];
+            let _: () = msg_send![label, setEditable: NO];
+            let _: () = msg_send![label, setSelectable: NO];
+            let white_color: id = msg_send![class!(NSColor), whiteColor];
+            let _: () = msg_send![label, setTextColor: white_color];
+            let _: () = msg_send![label, setAlignment: 1i64];
+            let font: id = msg_send![class!(NSFont), systemFontOfSize: 28.0];
+            let _: () = msg_send![label, setFont: font];
+            let _: () = msg_send![label, setAutoresizingMask: CENTERED_AUTORESIZING];
+            let _: () = msg_send![content_view, addSubview: label];
+
+            // NSSecureTextField shows bullets instead of the PIN or passphrase.
+            let secure_field: id = msg_send![class!(NSSecureTextField), alloc];
+            let field_frame = NSRect::new(
+                NSPoint::new(
+                    screen_frame.size.width / 2.0 - 150.0,
+                    screen_frame.size.height / 2.0,
+                ),
+                NSSize::new(300.0, 35.0),
+            );
+            let _: id = msg_send![secure_field, initWithFrame: field_frame];
+            let placeholder = NSString::alloc(nil).init_str(&super::prompt_hint());
+            let _: () = msg_send![secure_field, setPlaceholderString: placeholder];
+            let _: () = msg_send![secure_field, setBezeled: YES];
+            let _: () = msg_send![secure_field, setBezelStyle: 1i64];
+            let _: () = msg_send![secure_field, setDrawsBackground: YES];
+            let _: () = msg_send![secure_field, setEditable: YES];
+            let _: () = msg_send![secure_field, setSelectable: YES];
+
+            let bg_color: id = msg_send![class!(NSColor), whiteColor];
+            let _: () = msg_send![secure_field, setBackgroundColor: bg_color];
+            let text_color: id = msg_send![class!(NSColor), blackColor];
+            let _: () = msg_send![secure_field, setTextColor: text_color];
+
+            let _: () = msg_send![secure_field, setAutoresizingMask: CENTERED_AUTORESIZING];
+            let _: () = msg_send![content_view, addSubview: secure_field];
+            let _: () = msg_send![secure_field, becomeFirstResponder];
+
+            let button: id = msg_send![class!(NSButton), alloc];
+            let button_frame = NSRect::new(
+                NSPoint::new(
+                    screen_frame.size.width / 2.0 - 60.0,
+                    screen_frame.size.height / 2.0 - 50.0,
+                ),
+                NSSize::new(120.0, 32.0),
+            );
+            let _: id = msg_send![button, initWithFrame: button_frame];
+            let button_text = NSString::alloc(nil).init_str("Unlock");
+            let _: () = msg_send![button, setTitle: button_text];
+            let _: () = msg_send![button, setBezelStyle: 1i64];
+            let _: () = msg_send![button, setAutoresizingMask: CENTERED_AUTORESIZING];
+            let _: () = msg_send![content_view, addSubview: button];
+
+            // Read-only status lines under the button, filled in by refreshPanel:.
+            let panel_label: id = NSTextField::alloc(nil);
+            let panel_frame = NSRect::new(
+                NSPoint::new(
+                    screen_frame.size.width / 2.0 - 300.0,
+                    screen_frame.size.height / 2.0 - 250.0,
+                ),
+                NSSize::new(600.0, 180.0),
+            );
+            let _: id = msg_send![panel_label, initWithFrame: panel_frame];
+            let _: () = msg_send![panel_label, setBezeled: NO];
+            let _: () = msg_send![panel_label, setDrawsBackground: NO];
+            let _: () = msg_send![panel_label, setEditable: NO];
+            let _: () = msg_send![panel_label, setSelectable: NO];
+            let gray_color: id = msg_send![class!(NSColor), lightGrayColor];
+            let _: () = msg_send![panel_label, setTextColor: gray_color];
+            let _: () = msg_send![panel_label, setAlignment: 1i64];
+            let panel_font: id = msg_send![class!(NSFont), systemFontOfSize: 16.0];
+            let _: () = msg_send![panel_label, setFont: panel_font];
+            let _: () = msg_send![panel_label, setAutoresizingMask: CENTERED_AUTORESIZING];
+            let _: () = msg_send![content_view, addSubview: panel_label];
+
+            {
+                let mut state = self.state.borrow_mut();
+                state.app = app;
+                state.window = window;
+                state.secure_field = secure_field;
+                state.panel_label = panel_label;
+                state.panel_seen = 0;
+                cover_screens(&mut state);
+            }
+            self.observer = create_screen_observer(Rc::clone(&self.state));
+
+            let target = create_target(Rc::clone(&self.state));
+            self.target = target;
+            let _: () = msg_send![button, setTarget: target];
+            let _: () = msg_send![button, setAction: sel!(handleUnlock:)];
+            let _: () = msg_send![button, setKeyEquivalent: NSString::alloc(nil).init_str("\r")];
+
+            let _: () = msg_send![target, refreshPanel: nil];
+            self.panel_timer = msg_send![
+                class!(NSTimer),
+                scheduledTimerWithTimeInterval: PANEL_REFRESH.as_secs_f64()
+                target: target
+                selector: sel!(refreshPanel:)
+                userInfo: nil
+                repeats: YES
+            ];
+
+            let _: () = msg_send![app, activateIgnoringOtherApps: YES];
+            let _: () = msg_send![window, makeKeyAndOrderFront: nil];
+            let _: () = msg_send![window, makeFirstResponder: secure_field];
+
+            // Force window to become key
+            let _: () = msg_send![window, becomeKeyWindow];
+            let _: () = msg_send![window, becomeMainWindow];
+            let _: () = msg_send![secure_field, selectText: nil];
+
+            self.app = app;
+            self.window = window;
+            self.secure_field = secure_field;
+        }
+
+        Ok(())
+    }
+
+    /// Run the Cocoa event loop until the Unlock action stops it.
+    fn prompt(&mut self) -> Result<String> {
+        loop {
+            unsafe {
+                self.app.run();
+            }
+            if let Some(pin) = self.state.borrow_mut().submitted.take() {
+                return Ok(pin);
+            }
+        }
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        unsafe {
+            let empty = NSString::alloc(nil).init_str("");
+            let _: () = msg_send![self.secure_field, setStringValue: empty];
+            let message = NSString::alloc(nil).init_str(message);
+            let _: () = msg_send![self.secure_field, setPlaceholderString: message];
+            let _: () = msg_send![self.window, makeFirstResponder: self.secure_field];
+        }
+        Ok(())
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        if self.panel_timer != nil {
+            unsafe {
+                let _: () = msg_send![self.panel_timer, invalidate];
+            }
+            self.panel_timer = nil;
+        }
+        if self.observer != nil {
+            unsafe {
+                let center: id = msg_send![class!(NSNotificationCenter), defaultCenter];
+                let _: () = msg_send![center, removeObserver: self.observer];
+                release_with_state(self.observer);
+            }
+            self.observer = nil;
+        }
+        if self.target != nil {
+            unsafe { release_with_state(self.target) };
+            self.target = nil;
+        }
+        for blocker in self.state.borrow_mut().blockers.drain(..) {
+            unsafe {
+                let _: () = msg_send![blocker, close];
+            }
+        }
+        if self.window != nil {
+            unsafe {
+                let _: () = msg_send![self.window, close];
+            }
+            self.window = nil;
+        }
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        self.state.borrow_mut().panel = panel;
+    }
+}
+
+/// The screen with the menu bar, which macOS lists first.
+unsafe fn primary_screen() -> id {
+    let screens: id = msg_send![class!(NSScreen), screens];
+    let count: usize = msg_send![screens, count];
+    if count == 0 {
+        return NSScreen::mainScreen(nil);
+    }
+    msg_send![screens, objectAtIndex: 0usize]
+}
+
+/// Move the prompt window onto the primary screen and put a blocking window
+/// on every other screen, replacing any blockers from a previous layout.
+unsafe fn cover_screens(state: &mut UnlockState) {
+    for blocker in state.blockers.drain(..) {
+        let _: () = msg_send![blocker, close];
+    }
+
+    let screens: id = msg_send![class!(NSScreen), screens];
+    let count: usize = msg_send![screens, count];
+    for index in 0..count {
+        let screen: id = msg_send![screens, objectAtIndex: index];
+        let frame: NSRect = msg_send![screen, frame];
+        if index == 0 {
+            let _: () = msg_send![state.window, setFrame: frame display: YES];
+            let _: () = msg_send![state.window, orderFrontRegardless];
+        } else {
+            state.blockers.push(create_blocker(frame));
+        }
+    }
+}
+
+unsafe fn create_blocker(frame: NSRect) -> id {
+    let window: id = msg_send![class!(NSWindow), alloc];
+    let window: id = msg_send![
+        window,
+        initWithContentRect: frame
+        styleMask: NSWindowStyleMask::NSBorderlessWindowMask
+        backing: NSBackingStoreBuffered
+        defer: NO
+    ];
+    let _: () = msg_send![window, setLevel: 1000i32];
+    let black_color: id = msg_send![class!(NSColor), blackColor];
+    let _: () = msg_send![window, setBackgroundColor: black_color];
+    let _: () = msg_send![window, setCollectionBehavior: 1 << 10];
+    let _: () = msg_send![window, orderFrontRegardless];
+    window
+}
+
+/// The state behind the `state` ivar of a target or observer, if it has not
+/// been released yet.
+unsafe fn ivar_state(this: &Object) -> Option<&RefCell<UnlockState>> {
+    let state_ptr: *mut c_void = *this.get_ivar("state");
+    (state_ptr as *const RefCell<UnlockState>).as_ref()
+}
+
+/// Give back the reference to the state held by `object`, then release
+/// `object` itself.
+unsafe fn release_with_state(object: id) {
+    let state_ptr: *mut c_void = *(*object).get_ivar("state");
+    (*object).set_ivar("state", std::ptr::null_mut::<c_void>());
+    if !state_ptr.is_null() {
+        drop(Rc::from_raw(state_ptr as *const RefCell<UnlockState>));
+    }
+    let _: () = msg_send![object, release];
+}
+
+unsafe fn create_screen_observer(state: Rc<RefCell<UnlockState>>) -> id {
+    let superclass = class!(NSObject);
+    let mut decl = ClassDecl::new("ScreenObserver", superclass).unwrap();
+
+    extern "C" fn screens_changed(this: &Object, _cmd: Sel, _notification: id) {
+        unsafe {
+            if let Some(state) = ivar_state(this) {
+                cover_screens(&mut state.borrow_mut());
+            }
+        }
+    }
+
+    decl.add_ivar::<*mut c_void>("state");
+    decl.add_method(
+        sel!(screensChanged:),
+        screens_changed as extern "C" fn(&Object, Sel, id),
+    );
+
+    let observer_class = decl.register();
+    let observer: id = msg_send![observer_class, alloc];
+    let observer: id = msg_send![observer, init];
+
+    let state_ptr = Rc::into_raw(state) as *mut c_void;
+    (*observer).set_ivar("state", state_ptr);
+
+    let center: id = msg_send![class!(NSNotificationCenter), defaultCenter];
+    let name = NSString::alloc(nil).init_str(SCREEN_CHANGE_NOTIFICATION);
+    let _: () = msg_send![
+        center,
+        addObserver: observer
+        selector: sel!(screensChanged:)
+        name: name
+        object: nil
+    ];
+
+    observer
+}
+
+unsafe fn create_window_class() -> *const objc::runtime::Class {
+    let superclass = class!(NSWindow);
+    let mut decl = ClassDecl::new("LockWindow", superclass).unwrap();
+
+    extern "C" fn can_become_key(_this: &Object, _cmd: Sel) -> BOOL {
+        YES
+    }
+
+    extern "C" fn can_become_main(_this: &Object, _cmd: Sel) -> BOOL {
+        YES
+    }
+
+    decl.add_method(
+        sel!(canBecomeKeyWindow),
+        can_become_key as extern "C" fn(&Object, Sel) -> BOOL,
+    );
+
+    decl.add_method(
+        sel!(canBecomeMainWindow),
+        can_become_main as extern "C" fn(&Object, Sel) -> BOOL,
+    );
+
+    decl.register()
+}
+
+unsafe fn create_target(state: Rc<RefCell<UnlockState>>) -> id {
+    let superclass = class!(NSObject);
+    let mut decl = ClassDecl::new("UnlockTarget", superclass).unwrap();
+
+    extern "C" fn handle_unlock(this: &Object, _cmd: Sel, _sender: id) {
+        unsafe {
+            let Some(state) = ivar_state(this) else {
+                return;
+            };
+            let mut state = state.borrow_mut();
+            let string_value: id = msg_send![state.secure_field, stringValue];
+            let utf8_ptr: *const i8 = msg_send![string_value, UTF8String];
+            let pin = std::ffi::CStr::from_ptr(utf8_ptr)
+                .to_string_lossy()
+                .to_string();
+            state.submitted = Some(pin);
+            let _: () = msg_send![state.app, stop: nil];
+        }
+    }
+
+    extern "C" fn refresh_panel(this: &Object, _cmd: Sel, _timer: id) {
+        unsafe {
+            let Some(state) = ivar_state(this) else {
+                return;
+            };
+            let state = &mut *state.borrow_mut();
+            if state.panel.changed(&mut state.panel_seen) {
+                let text = NSString::alloc(nil).init_str(&state.panel.lines().join("\n"));
+                let _: () = msg_send![state.panel_label, setStringValue: text];
+            }
+        }
+    }
+
+    decl.add_ivar::<*mut c_void>("state");
+    decl.add_method(
+        sel!(handleUnlock:),
+        handle_unlock as extern "C" fn(&Object, Sel, id),
+    );
+    decl.add_method(
+        sel!(refreshPanel:),
+        refresh_panel as extern "C" fn(&Object, Sel, id),
+    );
+
+    let target_class = decl.register();
+    let target: id = msg_send![target_class, alloc];
+    let target: id = msg_send![target, init];
+
+    let state_ptr = Rc::into_raw(state) as *mut c_void;
+    (*target).set_ivar("state", state_ptr);
+
+    target
+}
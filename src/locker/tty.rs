@@ -0,0 +1,240 @@
+use super::keys::{apply_key, Key};
+use super::{wait_readable, InfoPanel, LockBackend, PANEL_REFRESH};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+
+const IGNORED_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTSTP, libc::SIGQUIT];
+
+/// Full-screen lock prompt on the controlling terminal, for SSH sessions and
+/// bare consoles. The terminal runs in raw mode, so Ctrl+C, Ctrl+Z and Ctrl+\
+/// arrive as plain bytes; the matching signals are ignored as well in case
+/// they are sent from elsewhere.
+pub struct TtyBackend {
+    tty: File,
+    saved_termios: Option<libc::termios>,
+    saved_handlers: Vec<(libc::c_int, libc::sighandler_t)>,
+    shown: bool,
+    input: String,
+    message: String,
+    hint: String,
+    panel: InfoPanel,
+    panel_seen: u64,
+}
+
+impl TtyBackend {
+    pub fn open() -> Result<Self> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .context("No controlling terminal to lock")?;
+        Ok(Self {
+            tty,
+            saved_termios: None,
+            saved_handlers: Vec::new(),
+            shown: false,
+            input: String::new(),
+            message: String::new(),
+            hint: super::prompt_hint(),
+            panel: InfoPanel::default(),
+            panel_seen: 0,
+        })
+    }
+
+    fn enter_raw_mode(&mut self) -> Result<()> {
+        let fd = self.tty.as_raw_fd();
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to read terminal mode");
+            }
+            self.saved_termios = Some(termios);
+
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSAFLUSH, &termios) != 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to set raw mode");
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> (u16, u16) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_row > 0 && size.ws_col > 0 {
+            (size.ws_row, size.ws_col)
+        } else {
+            (24, 80)
+        }
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        let (rows, cols) = self.size();
+        let masked = super::masked(&self.input);
+        let prompt = if masked.is_empty() {
+            &self.hint
+        } else {
+            &masked
+        };
+        let screen = render(rows, cols, prompt, &self.message, &self.panel.lines());
+        self.tty.write_all(screen.as_bytes())?;
+        self.tty.flush()?;
+        Ok(())
+    }
+}
+
+impl LockBackend for TtyBackend {
+    fn show(&mut self) -> Result<()> {
+        for signal in IGNORED_SIGNALS {
+            let previous = unsafe { libc::signal(signal, libc::SIG_IGN) };
+            self.saved_handlers.push((signal, previous));
+        }
+        self.enter_raw_mode()?;
+        self.shown = true;
+
+        // Switch to the alternate screen so the session's scrollback is hidden.
+        self.tty.write_all(b"\x1b[?1049h")?;
+        self.redraw()
+    }
+
+    fn prompt(&mut self) -> Result<String> {
+        let mut buf = [0u8; 64];
+        loop {
+            if !wait_readable(self.tty.as_raw_fd(), PANEL_REFRESH)? {
+                if self.panel.changed(&mut self.panel_seen) {
+                    self.redraw()?;
+                }
+                continue;
+            }
+            let n = self.tty.read(&mut buf)?;
+            if n == 0 {
+                anyhow::bail!("Terminal closed while locked");
+            }
+            for key in parse_input(&buf[..n]) {
+                if let Some(pin) = apply_key(&mut self.input, key) {
+                    self.redraw()?;
+                    return Ok(pin);
+                }
+            }
+            self.message.clear();
+            self.redraw()?;
+        }
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        self.input.clear();
+        self.message = message.to_string();
+        self.redraw()
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        if !self.shown {
+            return Ok(());
+        }
+        self.shown = false;
+
+        self.tty.write_all(b"\x1b[2J\x1b[H\x1b[?1049l")?;
+        self.tty.flush()?;
+        if let Some(termios) = self.saved_termios.take() {
+            unsafe {
+                libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSAFLUSH, &termios);
+            }
+        }
+        for (signal, handler) in self.saved_handlers.drain(..) {
+            unsafe {
+                libc::signal(signal, handler);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        self.panel = panel;
+    }
+}
+
+impl Drop for TtyBackend {
+    fn drop(&mut self) {
+        let _ = self.teardown();
+    }
+}
+
+/// Turn one read from the raw terminal into keys. Control characters become
+/// [`Key::Other`], which leaves the input untouched. A read that starts with
+/// ESC and carries more bytes is an escape sequence (arrows, function keys)
+/// and is dropped whole.
+fn parse_input(bytes: &[u8]) -> Vec<Key> {
+    if bytes.len() > 1 && bytes[0] == 0x1b {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .map(|c| match c {
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x1b' | '\x15' => Key::Escape,
+            c if c.is_control() || c == char::REPLACEMENT_CHARACTER => Key::Other,
+            c => Key::Char(c),
+        })
+        .collect()
+}
+
+fn render(rows: u16, cols: u16, prompt: &str, message: &str, panel: &[String]) -> String {
+    let middle = rows / 2;
+    let mut screen = String::from("\x1b[2J");
+    let mut line = |row: u16, text: &str| {
+        let width = text.chars().count() as u16;
+        let column = cols.saturating_sub(width) / 2 + 1;
+        screen.push_str(&format!("\x1b[{};{}H{}", row.max(1), column, text));
+    };
+    line(middle.saturating_sub(2), "Screen Locked");
+    if !message.is_empty() {
+        line(middle + 2, message);
+    }
+    for (index, text) in panel.iter().enumerate() {
+        line(middle + 4 + index as u16, text);
+    }
+    line(middle, prompt);
+    screen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(
+            parse_input(b"12\x7f3\r"),
+            vec![
+                Key::Char('1'),
+                Key::Char('2'),
+                Key::Backspace,
+                Key::Char('3'),
+                Key::Enter
+            ]
+        );
+        // Ctrl+C, Ctrl+Z and Ctrl+\ are ignored rather than interrupting.
+        assert_eq!(parse_input(b"\x03\x1a\x1c"), vec![Key::Other; 3]);
+        assert!(parse_input(b"\x1b[A").is_empty());
+        assert_eq!(parse_input(b"\x1b"), vec![Key::Escape]);
+    }
+
+    #[test]
+    fn test_render_centers_prompt() {
+        let panel = ["agent exited with code 0".to_string()];
+        let screen = render(24, 80, "****", "Incorrect PIN", &panel);
+        assert!(screen.starts_with("\x1b[2J"));
+        assert!(screen.contains("\x1b[10;34HScreen Locked"));
+        assert!(screen.contains("\x1b[14;34HIncorrect PIN"));
+        assert!(screen.contains("\x1b[16;29Hagent exited with code 0"));
+        assert!(screen.ends_with("\x1b[12;39H****"));
+    }
+}
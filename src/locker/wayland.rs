@@ -0,0 +1,766 @@
+use super::keys::{apply_key, key_from_keysym, Key};
+use super::{wait_readable, InfoPanel, LockBackend, PANEL_REFRESH};
+use anyhow::{Context, Result};
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::{Drawable, Pixel};
+use rustix::fs::{memfd_create, MemfdFlags};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::Duration;
+use wayland_client::backend::WaylandError;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_keyboard, wl_output, wl_registry, wl_seat, wl_shm, wl_shm_pool,
+    wl_surface,
+};
+use wayland_client::{
+    delegate_noop, Connection, Dispatch, DispatchError, EventQueue, QueueHandle, WEnum,
+};
+use wayland_protocols::ext::session_lock::v1::client::{
+    ext_session_lock_manager_v1, ext_session_lock_surface_v1, ext_session_lock_v1,
+};
+use xkbcommon_dl::{
+    xkb_context, xkb_context_flags, xkb_keymap, xkb_keymap_compile_flags, xkb_keymap_format,
+    xkb_state, xkbcommon_option, XkbCommon,
+};
+
+/// A session lock held through `ext-session-lock-v1`. While it is held the
+/// compositor shows only our lock surfaces and routes all input to them; if
+/// the process dies, the session stays locked.
+pub struct WaylandLocker {
+    conn: Connection,
+    queue: EventQueue<State>,
+    state: State,
+}
+
+impl WaylandLocker {
+    pub fn connect() -> Result<Self> {
+        // Keys are translated through libxkbcommon; without it the session
+        // would lock with no way to type the PIN.
+        xkbcommon_option()
+            .context("Failed to load libxkbcommon, which is needed to read the keyboard")?;
+        let conn = Connection::connect_to_env().context("Failed to connect to Wayland")?;
+        let (globals, queue) =
+            registry_queue_init::<State>(&conn).context("Failed to list Wayland globals")?;
+        let qh = queue.handle();
+
+        let compositor: wl_compositor::WlCompositor = globals
+            .bind(&qh, 4..=6, ())
+            .context("Compositor does not provide wl_compositor")?;
+        let shm: wl_shm::WlShm = globals
+            .bind(&qh, 1..=1, ())
+            .context("Compositor does not provide wl_shm")?;
+        let lock_manager: ext_session_lock_manager_v1::ExtSessionLockManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .context("Compositor does not support ext-session-lock-v1")?;
+
+        let mut state = State {
+            compositor,
+            shm,
+            lock_manager,
+            lock: None,
+            surfaces: Vec::new(),
+            pending_outputs: Vec::new(),
+            xkb: None,
+            keyboard_error: None,
+            input: String::new(),
+            message: String::new(),
+            hint: super::prompt_hint(),
+            panel: InfoPanel::default(),
+            panel_seen: 0,
+            submitted: None,
+            locked: false,
+            finished: false,
+        };
+
+        let registry = globals.registry();
+        globals.contents().with_list(|list| {
+            for global in list {
+                match global.interface.as_str() {
+                    "wl_output" => {
+                        let output = registry.bind::<wl_output::WlOutput, _, _>(
+                            global.name,
+                            global.version.min(4),
+                            &qh,
+                            (),
+                        );
+                        state.pending_outputs.push((global.name, output));
+                    }
+                    "wl_seat" => {
+                        registry.bind::<wl_seat::WlSeat, _, _>(
+                            global.name,
+                            global.version.min(7),
+                            &qh,
+                            (),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self { conn, queue, state })
+    }
+
+    /// Dispatch whatever arrives within `timeout`, returning early once
+    /// events have been handled.
+    fn dispatch_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.queue.dispatch_pending(&mut self.state)?;
+        self.queue.flush()?;
+        if let Some(guard) = self.queue.prepare_read() {
+            if wait_readable(guard.connection_fd().as_raw_fd(), timeout)? {
+                match guard.read() {
+                    Ok(_) => {}
+                    Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(DispatchError::from(e).into()),
+                }
+            }
+        }
+        self.queue.dispatch_pending(&mut self.state)?;
+        Ok(())
+    }
+}
+
+impl LockBackend for WaylandLocker {
+    /// Lock the session and wait until the compositor confirms it, with a
+    /// lock surface on every output.
+    fn show(&mut self) -> Result<()> {
+        let qh = self.queue.handle();
+        self.state.lock = Some(self.state.lock_manager.lock(&qh, ()));
+        for (name, output) in std::mem::take(&mut self.state.pending_outputs) {
+            self.state.add_output(name, output, &qh);
+        }
+
+        while !self.state.locked {
+            self.queue.blocking_dispatch(&mut self.state)?;
+            if self.state.finished {
+                anyhow::bail!("Compositor refused the session lock (is another locker running?)");
+            }
+            self.state.check_keyboard()?;
+        }
+        Ok(())
+    }
+
+    /// Process events until the user submits a PIN with Enter, redrawing
+    /// when the panel changes in between.
+    fn prompt(&mut self) -> Result<String> {
+        loop {
+            if let Some(pin) = self.state.submitted.take() {
+                return Ok(pin);
+            }
+            self.dispatch_timeout(PANEL_REFRESH)?;
+            if self.state.finished {
+                anyhow::bail!("Compositor ended the session lock");
+            }
+            self.state.check_keyboard()?;
+            if self.state.panel.changed(&mut self.state.panel_seen) {
+                self.state.redraw_all(&self.queue.handle())?;
+                self.conn.flush()?;
+            }
+        }
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        self.state.input.clear();
+        self.state.message = message.to_string();
+        self.state.redraw_all(&self.queue.handle())?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        for surface in self.state.surfaces.drain(..) {
+            surface.lock_surface.destroy();
+            surface.surface.destroy();
+        }
+        if let Some(lock) = self.state.lock.take() {
+            // Unlocking is only allowed once the compositor confirmed the
+            // lock and has not ended it; otherwise the lock is just dropped.
+            if self.state.locked && !self.state.finished {
+                lock.unlock_and_destroy();
+            } else {
+                lock.destroy();
+            }
+        }
+        self.conn.roundtrip()?;
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        self.state.panel = panel;
+    }
+}
+
+struct LockSurface {
+    output_name: u32,
+    surface: wl_surface::WlSurface,
+    lock_surface: ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+    width: u32,
+    height: u32,
+}
+
+struct State {
+    compositor: wl_compositor::WlCompositor,
+    shm: wl_shm::WlShm,
+    lock_manager: ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+    lock: Option<ext_session_lock_v1::ExtSessionLockV1>,
+    surfaces: Vec<LockSurface>,
+    pending_outputs: Vec<(u32, wl_output::WlOutput)>,
+    xkb: Option<Xkb>,
+    /// Why the keyboard cannot be read, if it cannot. Ends the lock rather
+    /// than leaving a session nobody can unlock.
+    keyboard_error: Option<String>,
+    input: String,
+    message: String,
+    hint: String,
+    panel: InfoPanel,
+    panel_seen: u64,
+    submitted: Option<String>,
+    locked: bool,
+    finished: bool,
+}
+
+impl State {
+    fn check_keyboard(&mut self) -> Result<()> {
+        match self.keyboard_error.take() {
+            Some(error) => anyhow::bail!("{}", error),
+            None => Ok(()),
+        }
+    }
+
+    /// Take the keymap the compositor sent with the keyboard.
+    fn load_keymap(&mut self, keymap: std::io::Result<Vec<u8>>) {
+        self.xkb = match keymap {
+            Ok(keymap) => Xkb::new(&keymap),
+            Err(_) => None,
+        };
+        if self.xkb.is_none() {
+            self.keyboard_error =
+                Some("Failed to load the keyboard keymap - is libxkbcommon installed?".to_string());
+        }
+    }
+
+    /// Apply a key press, given as an evdev key code.
+    fn handle_key(&mut self, evdev_code: u32, qh: &QueueHandle<State>) {
+        let Some(xkb) = &self.xkb else {
+            return;
+        };
+        let key = xkb.key(evdev_code);
+        if key == Key::Other {
+            return;
+        }
+        if let Some(pin) = apply_key(&mut self.input, key) {
+            self.submitted = Some(pin);
+        }
+        self.message.clear();
+        if let Err(e) = self.redraw_all(qh) {
+            eprintln!("Failed to draw lock surface: {}", e);
+        }
+    }
+
+    fn add_output(&mut self, name: u32, output: wl_output::WlOutput, qh: &QueueHandle<State>) {
+        let Some(lock) = &self.lock else {
+            self.pending_outputs.push((name, output));
+            return;
+        };
+        let surface = self.compositor.create_surface(qh, ());
+        let lock_surface = lock.get_lock_surface(&surface, &output, qh, name);
+        self.surfaces.push(LockSurface {
+            output_name: name,
+            surface,
+            lock_surface,
+            width: 0,
+            height: 0,
+        });
+    }
+
+    fn redraw_all(&self, qh: &QueueHandle<State>) -> Result<()> {
+        for surface in &self.surfaces {
+            self.draw(surface, qh)?;
+        }
+        Ok(())
+    }
+
+    fn draw(&self, target: &LockSurface, qh: &QueueHandle<State>) -> Result<()> {
+        if target.width == 0 || target.height == 0 {
+            return Ok(());
+        }
+
+        let masked = super::masked(&self.input);
+        let prompt = if masked.is_empty() {
+            &self.hint
+        } else {
+            &masked
+        };
+        let canvas = render(
+            target.width,
+            target.height,
+            prompt,
+            &self.message,
+            &self.panel.lines(),
+        );
+
+        let fd = memfd_create("agent-lock", MemfdFlags::CLOEXEC)
+            .context("Failed to create shared memory buffer")?;
+        let mut file = File::from(fd);
+        file.write_all(&canvas.pixels)?;
+
+        let pool = self
+            .shm
+            .create_pool(file.as_fd(), canvas.pixels.len() as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            target.width as i32,
+            target.height as i32,
+            target.width as i32 * 4,
+            wl_shm::Format::Xrgb8888,
+            qh,
+            (),
+        );
+        pool.destroy();
+
+        target.surface.attach(Some(&buffer), 0, 0);
+        target
+            .surface
+            .damage_buffer(0, 0, target.width as i32, target.height as i32);
+        target.surface.commit();
+        Ok(())
+    }
+}
+
+/// XRGB8888 pixel buffer that embedded-graphics can draw text into.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Canvas {
+    type Color = Rgb888;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 {
+                continue;
+            }
+            let (x, y) = (point.x as u32, point.y as u32);
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            let offset = ((y * self.width + x) * 4) as usize;
+            self.pixels[offset..offset + 4].copy_from_slice(&[
+                color.b(),
+                color.g(),
+                color.r(),
+                0xff,
+            ]);
+        }
+        Ok(())
+    }
+}
+
+fn render(width: u32, height: u32, prompt: &str, message: &str, panel: &[String]) -> Canvas {
+    let mut canvas = Canvas {
+        width,
+        height,
+        pixels: vec![0; (width * height * 4) as usize],
+    };
+    let style = MonoTextStyle::new(&FONT_10X20, Rgb888::WHITE);
+    let center = Point::new(width as i32 / 2, height as i32 / 2);
+
+    let mut lines = vec![("Screen Locked", -60), (prompt, 0), (message, 50)];
+    for (index, text) in panel.iter().enumerate() {
+        lines.push((text, 100 + index as i32 * 30));
+    }
+    for (text, offset) in lines {
+        if text.is_empty() {
+            continue;
+        }
+        let position = center + Point::new(0, offset);
+        let _ = Text::with_alignment(text, position, style, Alignment::Center).draw(&mut canvas);
+    }
+    canvas
+}
+
+/// Keymap state from the compositor, translated through the system's
+/// libxkbcommon so the PIN is read with the user's keyboard layout.
+struct Xkb {
+    lib: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+}
+
+impl Xkb {
+    fn new(keymap: &[u8]) -> Option<Self> {
+        let lib = xkbcommon_option()?;
+        let text = CString::new(keymap.split(|&b| b == 0).next()?).ok()?;
+        unsafe {
+            let context = (lib.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None;
+            }
+            let keymap = (lib.xkb_keymap_new_from_string)(
+                context,
+                text.as_ptr(),
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                (lib.xkb_context_unref)(context);
+                return None;
+            }
+            let state = (lib.xkb_state_new)(keymap);
+            if state.is_null() {
+                (lib.xkb_keymap_unref)(keymap);
+                (lib.xkb_context_unref)(context);
+                return None;
+            }
+            Some(Self {
+                lib,
+                context,
+                keymap,
+                state,
+            })
+        }
+    }
+
+    fn update_mask(&self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        unsafe {
+            (self.lib.xkb_state_update_mask)(self.state, depressed, latched, locked, 0, 0, group);
+        }
+    }
+
+    /// Translate an evdev key code into a key, preferring the layout's text
+    /// for printable characters.
+    fn key(&self, evdev_code: u32) -> Key {
+        // XKB key codes are offset by 8 from evdev codes.
+        let code = evdev_code + 8;
+        let keysym = unsafe { (self.lib.xkb_state_key_get_one_sym)(self.state, code) };
+        match key_from_keysym(keysym) {
+            Key::Char(_) | Key::Other => {
+                let mut buf = [0u8; 8];
+                let len = unsafe {
+                    (self.lib.xkb_state_key_get_utf8)(
+                        self.state,
+                        code,
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                    )
+                };
+                let text = std::str::from_utf8(&buf[..len.clamp(0, 7) as usize]).unwrap_or("");
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if !c.is_control() => Key::Char(c),
+                    _ => Key::Other,
+                }
+            }
+            key => key,
+        }
+    }
+}
+
+impl Drop for Xkb {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_keymap_unref)(self.keymap);
+            (self.lib.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // A monitor plugged in while locked needs its own lock surface,
+            // otherwise the compositor would have nothing to show on it.
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == "wl_output" => {
+                let output =
+                    registry.bind::<wl_output::WlOutput, _, _>(name, version.min(4), qh, ());
+                state.add_output(name, output, qh);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                if let Some(index) = state.surfaces.iter().position(|s| s.output_name == name) {
+                    let surface = state.surfaces.remove(index);
+                    surface.lock_surface.destroy();
+                    surface.surface.destroy();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ext_session_lock_v1::ExtSessionLockV1,
+        event: ext_session_lock_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_session_lock_v1::Event::Locked => state.locked = true,
+            ext_session_lock_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, u32> for State {
+    fn event(
+        state: &mut Self,
+        lock_surface: &ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+        event: ext_session_lock_surface_v1::Event,
+        output_name: &u32,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let ext_session_lock_surface_v1::Event::Configure {
+            serial,
+            width,
+            height,
+        } = event
+        {
+            lock_surface.ack_configure(serial);
+            let Some(index) = state
+                .surfaces
+                .iter()
+                .position(|s| s.output_name == *output_name)
+            else {
+                return;
+            };
+            state.surfaces[index].width = width;
+            state.surfaces[index].height = height;
+            if let Err(e) = state.draw(&state.surfaces[index], qh) {
+                eprintln!("Failed to draw lock surface: {}", e);
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn event(
+        _: &mut Self,
+        seat: &wl_seat::WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        {
+            if capabilities.contains(wl_seat::Capability::Keyboard) {
+                seat.get_keyboard(qh, ());
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_keyboard::WlKeyboard, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_keyboard::WlKeyboard,
+        event: wl_keyboard::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_keyboard::Event::Keymap { fd, size, .. } => {
+                let mut keymap = Vec::with_capacity(size as usize);
+                let read = File::from(fd).take(size as u64).read_to_end(&mut keymap);
+                state.load_keymap(read.map(|_| keymap));
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                if let Some(xkb) = &state.xkb {
+                    xkb.update_mask(mods_depressed, mods_latched, mods_locked, group);
+                }
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: WEnum::Value(wl_keyboard::KeyState::Pressed),
+                ..
+            } => state.handle_key(key, qh),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, ()> for State {
+    fn event(
+        _: &mut Self,
+        buffer: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            buffer.destroy();
+        }
+    }
+}
+
+delegate_noop!(State: wl_compositor::WlCompositor);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: ext_session_lock_manager_v1::ExtSessionLockManagerV1);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_surface::WlSurface);
+delegate_noop!(State: ignore wl_output::WlOutput);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_draws_text_centered() {
+        let canvas = render(400, 300, "****", "", &[]);
+        assert_eq!(canvas.pixels.len(), 400 * 300 * 4);
+
+        let lit_rows = lit_rows(&canvas);
+        assert!(!lit_rows.is_empty());
+        assert!(lit_rows.iter().all(|&y| (60..200).contains(&y)));
+    }
+
+    #[test]
+    fn test_render_draws_panel_below_prompt() {
+        let canvas = render(400, 300, "****", "", &["agent running".to_string()]);
+        assert!(lit_rows(&canvas).iter().any(|&y| y >= 230));
+    }
+
+    fn lit_rows(canvas: &Canvas) -> Vec<u32> {
+        (0..canvas.height)
+            .filter(|y| {
+                (0..canvas.width).any(|x| canvas.pixels[((y * canvas.width + x) * 4) as usize] != 0)
+            })
+            .collect()
+    }
+
+    // evdev key codes.
+    const KEY_1: u32 = 2;
+    const KEY_4: u32 = 5;
+    const KEY_A: u32 = 30;
+    const KEY_ENTER: u32 = 28;
+
+    /// A `us` keymap as a compositor would send it, built from the system's
+    /// xkeyboard-config.
+    fn us_keymap() -> Vec<u8> {
+        let lib = xkbcommon_option().expect("libxkbcommon is not installed");
+        let layout = CString::new("us").unwrap();
+        let names = xkbcommon_dl::xkb_rule_names {
+            rules: std::ptr::null(),
+            model: std::ptr::null(),
+            layout: layout.as_ptr(),
+            variant: std::ptr::null(),
+            options: std::ptr::null(),
+        };
+        unsafe {
+            let context = (lib.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            let keymap = (lib.xkb_keymap_new_from_names)(
+                context,
+                &names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            assert!(!keymap.is_null(), "xkeyboard-config has no us layout");
+            let text = (lib.xkb_keymap_get_as_string)(
+                keymap,
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+            );
+            let bytes = std::ffi::CStr::from_ptr(text).to_bytes_with_nul().to_vec();
+            libc::free(text as *mut _);
+            (lib.xkb_keymap_unref)(keymap);
+            (lib.xkb_context_unref)(context);
+            bytes
+        }
+    }
+
+    #[test]
+    #[ignore = "needs libxkbcommon and xkeyboard-config"]
+    fn test_keymap_translates_keys() {
+        let xkb = Xkb::new(&us_keymap()).unwrap();
+        let mut input = String::new();
+        for code in [KEY_1, KEY_4, KEY_A] {
+            apply_key(&mut input, xkb.key(code));
+        }
+        // Shift is bit 0 of the depressed modifiers in the standard keymaps.
+        xkb.update_mask(1, 0, 0, 0);
+        apply_key(&mut input, xkb.key(KEY_A));
+        xkb.update_mask(0, 0, 0, 0);
+        assert_eq!(
+            apply_key(&mut input, xkb.key(KEY_ENTER)),
+            Some("14aA".to_string())
+        );
+
+        assert!(Xkb::new(b"not a keymap\0").is_none());
+    }
+
+    /// Runs against a compositor with ext-session-lock-v1, e.g.
+    /// `WLR_BACKENDS=headless sway`, then types a PIN through the keymap
+    /// the compositor sent.
+    #[test]
+    #[ignore = "needs a Wayland compositor with ext-session-lock-v1 in WAYLAND_DISPLAY"]
+    fn test_session_lock_under_headless_compositor() {
+        let mut locker = WaylandLocker::connect().unwrap();
+        locker.show().unwrap();
+        assert!(locker.state.locked);
+        assert!(!locker.state.surfaces.is_empty());
+
+        locker.queue.roundtrip(&mut locker.state).unwrap();
+        assert!(locker
+            .state
+            .surfaces
+            .iter()
+            .all(|s| s.width > 0 && s.height > 0));
+        assert!(locker.state.xkb.is_some(), "compositor sent no keymap");
+
+        let qh = locker.queue.handle();
+        for code in [KEY_1, KEY_4, KEY_ENTER] {
+            locker.state.handle_key(code, &qh);
+        }
+        assert_eq!(locker.prompt().unwrap(), "14");
+
+        locker.teardown().unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+use super::keys::{apply_key, Key};
+use super::{InfoPanel, LockBackend, PANEL_REFRESH};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{BOOL, COLORREF, HWND, LPARAM, LRESULT, RECT, TRUE, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint, CreateFontW, DeleteObject, DrawTextW, EndPaint, EnumDisplayMonitors, FillRect,
+    GetMonitorInfoW, GetStockObject, InvalidateRect, SelectObject, SetBkMode, SetTextColor,
+    BLACK_BRUSH, CLEARTYPE_QUALITY, CLIP_DEFAULT_PRECIS, DEFAULT_CHARSET, DEFAULT_PITCH, DT_CENTER,
+    DT_SINGLELINE, DT_VCENTER, FW_NORMAL, HBRUSH, HDC, HMONITOR, MONITORINFO, OUT_DEFAULT_PRECIS,
+    PAINTSTRUCT, TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetAsyncKeyState, SetFocus, VIRTUAL_KEY, VK_APPS, VK_CONTROL, VK_ESCAPE, VK_F4, VK_LWIN,
+    VK_RWIN, VK_TAB,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
+    GetClientRect, GetMessageW, KillTimer, LoadCursorW, RegisterClassW, SetForegroundWindow,
+    SetTimer, SetWindowPos, SetWindowsHookExW, ShowWindow, TranslateMessage, UnhookWindowsHookEx,
+    UnregisterClassW, HC_ACTION, HHOOK, HWND_TOPMOST, IDC_ARROW, KBDLLHOOKSTRUCT, LLKHF_ALTDOWN,
+    MONITORINFOF_PRIMARY, MSG, SWP_NOMOVE, SWP_NOSIZE, SW_SHOW, WH_KEYBOARD_LL, WM_CHAR, WM_CLOSE,
+    WM_PAINT, WM_TIMER, WNDCLASSW, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP, WS_VISIBLE,
+};
+
+const CLASS_NAME: PCWSTR = w!("AgentLockOverlay");
+const RAISE_TIMER_ID: usize = 1;
+const RAISE_INTERVAL_MS: u32 = PANEL_REFRESH.as_millis() as u32;
+
+/// Input typed into the overlay, shared with the window procedure. All
+/// windows and the keyboard hook run on the locking thread.
+#[derive(Default)]
+struct OverlayState {
+    input: String,
+    message: String,
+    hint: String,
+    submitted: Option<String>,
+    primary: HWND,
+    panel: InfoPanel,
+    panel_seen: u64,
+}
+
+thread_local! {
+    static OVERLAY: RefCell<OverlayState> = RefCell::new(OverlayState::default());
+}
+
+/// Shortcuts the low-level keyboard hook swallows while locked. Ctrl+Alt+Del
+/// is handled by the secure desktop and cannot be intercepted.
+fn is_blocked_shortcut(vk: VIRTUAL_KEY, alt: bool, ctrl: bool) -> bool {
+    matches!(vk, VK_LWIN | VK_RWIN | VK_APPS)
+        || (alt && matches!(vk, VK_TAB | VK_ESCAPE | VK_F4))
+        || (ctrl && vk == VK_ESCAPE)
+}
+
+fn key_from_char(c: u32) -> Key {
+    match c {
+        0x0d => Key::Enter,
+        0x08 => Key::Backspace,
+        0x1b => Key::Escape,
+        _ => match char::from_u32(c) {
+            Some(c) if !c.is_control() => Key::Char(c),
+            _ => Key::Other,
+        },
+    }
+}
+
+/// Topmost borderless windows covering every monitor, plus a low-level
+/// keyboard hook that blocks task switching and the Start menu.
+pub struct WindowsLocker {
+    windows: Vec<HWND>,
+    hook: Option<HHOOK>,
+}
+
+impl WindowsLocker {
+    pub fn new() -> Self {
+        Self {
+            windows: Vec::new(),
+            hook: None,
+        }
+    }
+
+    fn redraw(&self) {
+        for &hwnd in &self.windows {
+            unsafe {
+                InvalidateRect(hwnd, None, TRUE);
+            }
+        }
+    }
+}
+
+impl LockBackend for WindowsLocker {
+    fn show(&mut self) -> Result<()> {
+        let hint = super::prompt_hint();
+        OVERLAY.with(|state| state.borrow_mut().hint = hint);
+        unsafe {
+            let instance = GetModuleHandleW(None).context("Failed to get module handle")?;
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance.into(),
+                hCursor: LoadCursorW(None, IDC_ARROW)?,
+                lpszClassName: CLASS_NAME,
+                ..Default::default()
+            };
+            if RegisterClassW(&class) == 0 {
+                anyhow::bail!("Failed to register overlay window class");
+            }
+
+            let mut monitors: Vec<(RECT, bool)> = Vec::new();
+            EnumDisplayMonitors(
+                None,
+                None,
+                Some(collect_monitor),
+                LPARAM(&mut monitors as *mut _ as isize),
+            );
+            if monitors.is_empty() {
+                anyhow::bail!("No monitors found");
+            }
+
+            for (rect, primary) in monitors {
+                let hwnd = CreateWindowExW(
+                    WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
+                    CLASS_NAME,
+                    w!("agent-lock"),
+                    WS_POPUP | WS_VISIBLE,
+                    rect.left,
+                    rect.top,
+                    rect.right - rect.left,
+                    rect.bottom - rect.top,
+                    None,
+                    None,
+                    instance,
+                    None,
+                );
+                if hwnd.0 == 0 {
+                    anyhow::bail!("Failed to create overlay window");
+                }
+                let _ = ShowWindow(hwnd, SW_SHOW);
+                if primary {
+                    OVERLAY.with(|state| state.borrow_mut().primary = hwnd);
+                }
+                self.windows.push(hwnd);
+            }
+
+            let primary = OVERLAY.with(|state| {
+                let mut state = state.borrow_mut();
+                if state.primary.0 == 0 {
+                    state.primary = self.windows[0];
+                }
+                state.primary
+            });
+
+            self.hook = Some(
+                SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), instance, 0)
+                    .context("Failed to install keyboard hook")?,
+            );
+
+            SetTimer(primary, RAISE_TIMER_ID, RAISE_INTERVAL_MS, None);
+            raise(&self.windows, primary);
+        }
+        Ok(())
+    }
+
+    /// Pump messages until the user submits a PIN with Enter.
+    fn prompt(&mut self) -> Result<String> {
+        let mut msg = MSG::default();
+        loop {
+            if let Some(pin) = OVERLAY.with(|state| state.borrow_mut().submitted.take()) {
+                return Ok(pin);
+            }
+            unsafe {
+                let result = GetMessageW(&mut msg, None, 0, 0);
+                if result.0 <= 0 {
+                    anyhow::bail!("Overlay message loop ended");
+                }
+                let _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        OVERLAY.with(|state| {
+            let mut state = state.borrow_mut();
+            state.input.clear();
+            state.message = message.to_string();
+        });
+        self.redraw();
+        Ok(())
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        unsafe {
+            if let Some(hook) = self.hook.take() {
+                UnhookWindowsHookEx(hook)?;
+            }
+            let primary = OVERLAY.with(|state| std::mem::take(&mut *state.borrow_mut()).primary);
+            let _ = KillTimer(primary, RAISE_TIMER_ID);
+            for hwnd in self.windows.drain(..) {
+                DestroyWindow(hwnd)?;
+            }
+            if let Ok(instance) = GetModuleHandleW(None) {
+                let _ = UnregisterClassW(CLASS_NAME, instance);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        OVERLAY.with(|state| state.borrow_mut().panel = panel);
+    }
+}
+
+/// Keep every overlay above other topmost windows and give the primary one
+/// keyboard focus.
+unsafe fn raise(windows: &[HWND], primary: HWND) {
+    for &hwnd in windows {
+        let _ = SetWindowPos(hwnd, HWND_TOPMOST, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE);
+    }
+    let _ = SetForegroundWindow(primary);
+    let _ = SetFocus(primary);
+}
+
+unsafe extern "system" fn collect_monitor(
+    monitor: HMONITOR,
+    _: HDC,
+    _: *mut RECT,
+    data: LPARAM,
+) -> BOOL {
+    let monitors = &mut *(data.0 as *mut Vec<(RECT, bool)>);
+    let mut info = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    if GetMonitorInfoW(monitor, &mut info).as_bool() {
+        monitors.push((info.rcMonitor, info.dwFlags & MONITORINFOF_PRIMARY != 0));
+    }
+    TRUE
+}
+
+unsafe extern "system" fn keyboard_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        let vk = VIRTUAL_KEY(info.vkCode as u16);
+        let alt = info.flags.0 & LLKHF_ALTDOWN.0 != 0;
+        let ctrl = GetAsyncKeyState(VK_CONTROL.0 as i32) < 0;
+        if is_blocked_shortcut(vk, alt, ctrl) {
+            return LRESULT(1);
+        }
+    }
+    CallNextHookEx(None, code, wparam, lparam)
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_PAINT => {
+            paint(hwnd);
+            LRESULT(0)
+        }
+        WM_CHAR => {
+            let key = key_from_char(wparam.0 as u32);
+            if key != Key::Other {
+                OVERLAY.with(|state| {
+                    let mut state = state.borrow_mut();
+                    if let Some(pin) = apply_key(&mut state.input, key) {
+                        state.submitted = Some(pin);
+                    }
+                    state.message.clear();
+                });
+                InvalidateRect(hwnd, None, TRUE);
+            }
+            LRESULT(0)
+        }
+        // The raise timer doubles as the panel refresh, since it runs on the
+        // primary window where the panel is drawn.
+        WM_TIMER if wparam.0 == RAISE_TIMER_ID => {
+            let _ = SetWindowPos(hwnd, HWND_TOPMOST, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE);
+            let _ = SetForegroundWindow(hwnd);
+            let changed = OVERLAY.with(|state| {
+                let state = &mut *state.borrow_mut();
+                state.panel.changed(&mut state.panel_seen)
+            });
+            if changed {
+                InvalidateRect(hwnd, None, TRUE);
+            }
+            LRESULT(0)
+        }
+        // Alt+F4 is already swallowed by the hook; ignore any other close request.
+        WM_CLOSE => LRESULT(0),
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+unsafe fn paint(hwnd: HWND) {
+    let (is_primary, prompt, message, panel) = OVERLAY.with(|state| {
+        let state = state.borrow();
+        let masked = super::masked(&state.input);
+        let prompt = if masked.is_empty() {
+            state.hint.clone()
+        } else {
+            masked
+        };
+        (
+            state.primary == hwnd,
+            prompt,
+            state.message.clone(),
+            state.panel.lines(),
+        )
+    });
+
+    let mut ps = PAINTSTRUCT::default();
+    let hdc = BeginPaint(hwnd, &mut ps);
+    let mut client = RECT::default();
+    let _ = GetClientRect(hwnd, &mut client);
+    FillRect(hdc, &client, HBRUSH(GetStockObject(BLACK_BRUSH).0));
+
+    let font = CreateFontW(
+        32,
+        0,
+        0,
+        0,
+        FW_NORMAL.0 as i32,
+        0,
+        0,
+        0,
+        DEFAULT_CHARSET.0 as u32,
+        OUT_DEFAULT_PRECIS.0 as u32,
+        CLIP_DEFAULT_PRECIS.0 as u32,
+        CLEARTYPE_QUALITY.0 as u32,
+        DEFAULT_PITCH.0 as u32,
+        w!("Segoe UI"),
+    );
+    let previous_font = SelectObject(hdc, font);
+    SetBkMode(hdc, TRANSPARENT);
+    SetTextColor(hdc, COLORREF(0x00ff_ffff));
+
+    let middle = (client.top + client.bottom) / 2;
+    let mut lines = vec![("Screen Locked".to_string(), -60)];
+    if is_primary {
+        lines.push((prompt, 0));
+        if !message.is_empty() {
+            lines.push((message, 50));
+        }
+        for (index, text) in panel.into_iter().enumerate() {
+            lines.push((text, 100 + index as i32 * 36));
+        }
+    }
+    for (text, offset) in lines {
+        let mut wide: Vec<u16> = text.encode_utf16().collect();
+        let mut line = RECT {
+            left: client.left,
+            right: client.right,
+            top: middle + offset - 20,
+            bottom: middle + offset + 20,
+        };
+        DrawTextW(
+            hdc,
+            &mut wide,
+            &mut line,
+            DT_CENTER | DT_SINGLELINE | DT_VCENTER,
+        );
+    }
+
+    SelectObject(hdc, previous_font);
+    DeleteObject(font);
+    let _ = EndPaint(hwnd, &ps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_shortcuts() {
+        assert!(is_blocked_shortcut(VK_LWIN, false, false));
+        assert!(is_blocked_shortcut(VK_TAB, true, false));
+        assert!(is_blocked_shortcut(VK_ESCAPE, false, true));
+        assert!(!is_blocked_shortcut(VK_TAB, false, false));
+        assert!(!is_blocked_shortcut(VIRTUAL_KEY(b'1' as u16), false, false));
+    }
+
+    #[test]
+    fn test_key_from_char() {
+        assert_eq!(key_from_char('5' as u32), Key::Char('5'));
+        assert_eq!(key_from_char(0x0d), Key::Enter);
+        assert_eq!(key_from_char(0x08), Key::Backspace);
+        assert_eq!(key_from_char(0x01), Key::Other);
+    }
+}
@@ -0,0 +1,381 @@
+use super::keys::{apply_key, key_from_keysym, Key};
+use super::{wait_readable, InfoPanel, LockBackend, PANEL_REFRESH};
+use anyhow::{Context, Result};
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::Duration;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ConfigureWindowAux, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask, Font, Gcontext,
+    GrabMode, GrabStatus, InputFocus, KeyButMask, StackMode, Visibility, Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE};
+
+const FONTS: [&str; 2] = ["-*-*-bold-r-normal--24-*-*-*-*-*-iso8859-1", "fixed"];
+const GRAB_ATTEMPTS: u32 = 50;
+
+struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    fn load(conn: &RustConnection) -> Result<Self> {
+        let setup = conn.setup();
+        let (min, max) = (setup.min_keycode, setup.max_keycode);
+        let reply = conn
+            .get_keyboard_mapping(min, max - min + 1)?
+            .reply()
+            .context("Failed to read keyboard mapping")?;
+        Ok(Self {
+            min_keycode: min,
+            keysyms_per_keycode: reply.keysyms_per_keycode as usize,
+            keysyms: reply.keysyms,
+        })
+    }
+
+    fn lookup(&self, keycode: u8, shift: bool) -> u32 {
+        if keycode < self.min_keycode || self.keysyms_per_keycode == 0 {
+            return 0;
+        }
+        let base = (keycode - self.min_keycode) as usize * self.keysyms_per_keycode;
+        let unshifted = self.keysyms.get(base).copied().unwrap_or(0);
+        let shifted = if self.keysyms_per_keycode > 1 {
+            self.keysyms.get(base + 1).copied().unwrap_or(0)
+        } else {
+            0
+        };
+        if shift && shifted != 0 {
+            shifted
+        } else {
+            unshifted
+        }
+    }
+}
+
+struct Overlay {
+    window: Window,
+    gc: Gcontext,
+    width: u16,
+    height: u16,
+}
+
+/// Fullscreen override-redirect windows on every X screen, with the keyboard
+/// and pointer grabbed by the window on the default screen.
+pub struct X11Locker {
+    conn: RustConnection,
+    screen_num: usize,
+    font: Font,
+    char_width: i16,
+    overlays: Vec<Overlay>,
+    keymap: Keymap,
+    input: String,
+    message: String,
+    hint: String,
+    panel: InfoPanel,
+    panel_seen: u64,
+}
+
+impl X11Locker {
+    pub fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen_num) =
+            x11rb::connect(display).context("Failed to connect to the X server")?;
+
+        let font = conn.generate_id()?;
+        let mut opened = false;
+        for name in FONTS {
+            if conn.open_font(font, name.as_bytes())?.check().is_ok() {
+                opened = true;
+                break;
+            }
+        }
+        if !opened {
+            anyhow::bail!("No usable X11 font found");
+        }
+        let font_info = conn.query_font(font)?.reply()?;
+        let keymap = Keymap::load(&conn)?;
+
+        Ok(Self {
+            conn,
+            screen_num,
+            font,
+            char_width: font_info.max_bounds.character_width.max(1),
+            overlays: Vec::new(),
+            keymap,
+            input: String::new(),
+            message: String::new(),
+            hint: super::prompt_hint(),
+            panel: InfoPanel::default(),
+            panel_seen: 0,
+        })
+    }
+
+    /// Grab keyboard and pointer, retrying while another client (a menu,
+    /// a running drag) still holds them.
+    fn grab_input(&self) -> Result<()> {
+        let window = self.primary_window();
+
+        let mut keyboard = false;
+        let mut pointer = false;
+        for _ in 0..GRAB_ATTEMPTS {
+            if !keyboard {
+                let reply = self
+                    .conn
+                    .grab_keyboard(
+                        false,
+                        window,
+                        CURRENT_TIME,
+                        GrabMode::ASYNC,
+                        GrabMode::ASYNC,
+                    )?
+                    .reply()?;
+                keyboard = reply.status == GrabStatus::SUCCESS;
+            }
+            if !pointer {
+                let reply = self
+                    .conn
+                    .grab_pointer(
+                        false,
+                        window,
+                        EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE,
+                        GrabMode::ASYNC,
+                        GrabMode::ASYNC,
+                        window,
+                        NONE,
+                        CURRENT_TIME,
+                    )?
+                    .reply()?;
+                pointer = reply.status == GrabStatus::SUCCESS;
+            }
+            if keyboard && pointer {
+                self.conn
+                    .set_input_focus(InputFocus::POINTER_ROOT, window, CURRENT_TIME)?;
+                self.conn.flush()?;
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        anyhow::bail!(
+            "Failed to grab the {} - another application is holding it",
+            if keyboard { "pointer" } else { "keyboard" }
+        )
+    }
+
+    fn primary_window(&self) -> Window {
+        self.overlays
+            .get(self.screen_num)
+            .or_else(|| self.overlays.first())
+            .map(|overlay| overlay.window)
+            .unwrap_or(NONE)
+    }
+
+    fn redraw(&self) -> Result<()> {
+        let masked = super::masked(&self.input);
+        let prompt = if masked.is_empty() {
+            &self.hint
+        } else {
+            &masked
+        };
+
+        for overlay in &self.overlays {
+            self.conn
+                .clear_area(false, overlay.window, 0, 0, overlay.width, overlay.height)?;
+            let middle = (overlay.height / 2) as i16;
+            self.draw_centered(overlay, middle - 60, "Screen Locked")?;
+            if overlay.window == self.primary_window() {
+                self.draw_centered(overlay, middle, prompt)?;
+                if !self.message.is_empty() {
+                    self.draw_centered(overlay, middle + 50, &self.message)?;
+                }
+                for (index, text) in self.panel.lines().iter().enumerate() {
+                    self.draw_centered(overlay, middle + 100 + index as i16 * 30, text)?;
+                }
+            }
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn draw_centered(&self, overlay: &Overlay, y: i16, text: &str) -> Result<()> {
+        let bytes: Vec<u8> = text.bytes().take(255).collect();
+        let text_width = bytes.len() as i16 * self.char_width;
+        let x = (overlay.width as i16 - text_width).max(0) / 2;
+        self.conn
+            .image_text8(overlay.window, overlay.gc, x, y, &bytes)?;
+        Ok(())
+    }
+}
+
+impl LockBackend for X11Locker {
+    fn show(&mut self) -> Result<()> {
+        let roots = self.conn.setup().roots.clone();
+        for screen in &roots {
+            let window = self.conn.generate_id()?;
+            let aux = CreateWindowAux::new()
+                .override_redirect(1)
+                .background_pixel(screen.black_pixel)
+                .event_mask(
+                    EventMask::EXPOSURE
+                        | EventMask::KEY_PRESS
+                        | EventMask::BUTTON_PRESS
+                        | EventMask::VISIBILITY_CHANGE,
+                );
+            self.conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                screen.root,
+                0,
+                0,
+                screen.width_in_pixels,
+                screen.height_in_pixels,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                COPY_FROM_PARENT,
+                &aux,
+            )?;
+
+            let gc = self.conn.generate_id()?;
+            let gc_aux = CreateGCAux::new()
+                .foreground(screen.white_pixel)
+                .background(screen.black_pixel)
+                .font(self.font);
+            self.conn.create_gc(gc, window, &gc_aux)?;
+
+            self.conn.map_window(window)?;
+            self.conn.configure_window(
+                window,
+                &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+            )?;
+
+            self.overlays.push(Overlay {
+                window,
+                gc,
+                width: screen.width_in_pixels,
+                height: screen.height_in_pixels,
+            });
+        }
+        self.conn.flush()?;
+
+        self.grab_input()?;
+        self.redraw()
+    }
+
+    /// Process events until the user submits a PIN with Enter, redrawing
+    /// when the panel changes in between.
+    fn prompt(&mut self) -> Result<String> {
+        loop {
+            let Some(event) = self.conn.poll_for_event()? else {
+                if self.panel.changed(&mut self.panel_seen) {
+                    self.redraw()?;
+                }
+                wait_readable(self.conn.stream().as_raw_fd(), PANEL_REFRESH)?;
+                continue;
+            };
+            match event {
+                Event::Expose(event) if event.count == 0 => self.redraw()?,
+                Event::VisibilityNotify(event) if event.state != Visibility::UNOBSCURED => {
+                    self.conn.configure_window(
+                        event.window,
+                        &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+                    )?;
+                    self.conn.flush()?;
+                }
+                Event::MappingNotify(_) => self.keymap = Keymap::load(&self.conn)?,
+                Event::KeyPress(event) => {
+                    let shift = event.state.contains(KeyButMask::SHIFT);
+                    let key = key_from_keysym(self.keymap.lookup(event.detail, shift));
+                    if key == Key::Other {
+                        continue;
+                    }
+                    if let Some(pin) = apply_key(&mut self.input, key) {
+                        return Ok(pin);
+                    }
+                    self.message.clear();
+                    self.redraw()?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn unlock_failed(&mut self, message: &str) -> Result<()> {
+        self.input.clear();
+        self.message = message.to_string();
+        self.redraw()
+    }
+
+    fn unlock_succeeded(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        self.conn.ungrab_keyboard(CURRENT_TIME)?;
+        self.conn.ungrab_pointer(CURRENT_TIME)?;
+        for overlay in self.overlays.drain(..) {
+            self.conn.free_gc(overlay.gc)?;
+            self.conn.destroy_window(overlay.window)?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn set_panel(&mut self, panel: InfoPanel) {
+        self.panel = panel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::keys::XK_RETURN;
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use x11rb::protocol::xproto::{KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+    use x11rb::protocol::xtest::ConnectionExt as _;
+
+    fn type_keys(conn: &RustConnection, keymap: &Keymap, keysyms: &[u32]) {
+        let root = conn.setup().roots[0].root;
+        for &keysym in keysyms {
+            let keycode = (keymap.min_keycode..=conn.setup().max_keycode)
+                .find(|&code| keymap.lookup(code, false) == keysym)
+                .expect("keysym not in keymap");
+            conn.xtest_fake_input(KEY_PRESS_EVENT, keycode, CURRENT_TIME, root, 0, 0, 0)
+                .unwrap();
+            conn.xtest_fake_input(KEY_RELEASE_EVENT, keycode, CURRENT_TIME, root, 0, 0, 0)
+                .unwrap();
+            conn.flush().unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Runs against a real X server, e.g. `xvfb-run cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs an X server in DISPLAY"]
+    fn test_unlock_under_xvfb() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&attempts);
+        let locker = thread::spawn(move || {
+            let mut backend = X11Locker::connect(None)?;
+            super::super::run(&mut backend, |pin| {
+                recorded.lock().unwrap().push(pin.to_string());
+                pin == "1234"
+            })
+        });
+
+        let (conn, _) = x11rb::connect(None).unwrap();
+        let keymap = Keymap::load(&conn).unwrap();
+        thread::sleep(Duration::from_millis(500));
+
+        let digits = |pin: &str| pin.chars().map(|c| c as u32).collect::<Vec<_>>();
+        type_keys(&conn, &keymap, &digits("0000"));
+        type_keys(&conn, &keymap, &[XK_RETURN]);
+        type_keys(&conn, &keymap, &digits("1234"));
+        type_keys(&conn, &keymap, &[XK_RETURN]);
+
+        locker.join().unwrap().unwrap();
+        assert_eq!(*attempts.lock().unwrap(), vec!["0000", "1234"]);
+    }
+}
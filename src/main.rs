@@ -1,10 +1,32 @@
+mod audit;
 mod auth;
+mod awake;
+mod control;
+mod daemon;
+mod duration;
+mod duress;
+mod history;
+mod hotkey;
+mod idle;
 mod locker;
+mod monitor;
+mod notify;
+mod process;
+mod run;
+mod session;
 mod sleep_prevention;
+mod status;
+mod totp;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
+use audit::AuditEvent;
 use clap::{Parser, Subcommand};
-use sleep_prevention::SleepPreventer;
+use locker::{InfoPanel, LockBackend};
+use monitor::{PanelConfig, StatusMonitor};
+use session::{Session, SessionKind};
+use sleep_prevention::{SleepPreventer, SleepProfile, SleepStatus};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "agent-lock")]
@@ -16,146 +38,402 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    Setup,
-    Lock,
-    Daemon,
-    Status,
+    Setup {
+        #[command(flatten)]
+        credential: CredentialArgs,
+        /// Also require an authenticator app code (RFC 6238 TOTP): with the PIN, or instead of it
+        #[arg(long, value_enum, value_name = "MODE")]
+        totp: Option<auth::TotpMode>,
+    },
+    /// Change the PIN after entering the current one
+    ChangePin {
+        #[command(flatten)]
+        credential: CredentialArgs,
+    },
+    /// Set a new PIN without the current one (needs a recovery code or a reset-confirm file in the config directory)
+    Reset,
+    /// Replace the recovery codes after entering the current PIN
+    RecoveryCodes,
+    /// Enroll an authenticator app for unlocking, or turn it off
+    Totp {
+        /// What the lock screen asks for
+        #[arg(long, value_enum, required_unless_present = "off")]
+        mode: Option<auth::TotpMode>,
+        /// Stop asking for authenticator codes
+        #[arg(long, conflicts_with = "mode")]
+        off: bool,
+    },
+    /// Set a second PIN that unlocks as usual but quietly runs the given actions, or remove it
+    Duress {
+        /// Write the time of a duress unlock to this file
+        #[arg(long, value_name = "PATH")]
+        marker: Option<PathBuf>,
+        /// Stop this process (PID or name) on a duress unlock; repeat for more
+        #[arg(long = "stop", value_name = "PID|NAME")]
+        stop: Vec<String>,
+        /// Remove the duress PIN
+        #[arg(long, conflicts_with_all = ["marker", "stop", "hook"])]
+        off: bool,
+        /// Command to start on a duress unlock, with AGENT_LOCK_EVENT=duress set
+        #[arg(last = true, value_name = "HOOK")]
+        hook: Vec<String>,
+    },
+    Lock {
+        /// Lock the controlling terminal instead of showing a GUI overlay (for SSH and consoles)
+        #[arg(long)]
+        tty: bool,
+        /// What to keep awake while locked
+        #[arg(long, value_enum, default_value = "system+display+lid")]
+        sleep_profile: SleepProfile,
+        /// Show this process (PID or name) on the lock screen; repeat for more (adds to panel.json)
+        #[arg(long = "watch", value_name = "PID|NAME")]
+        watch: Vec<String>,
+        /// Show the last lines of this log file on the lock screen; repeat for more
+        #[arg(long = "tail", value_name = "PATH")]
+        tail: Vec<PathBuf>,
+    },
+    Daemon {
+        /// Lock hotkey such as ctrl+alt+l; repeat to list fallbacks in order (overrides daemon.json)
+        #[arg(long = "hotkey", value_name = "KEYS", value_parser = hotkey::parse_hotkey_arg)]
+        hotkeys: Vec<String>,
+        /// Lock automatically after this much time without keyboard or mouse input (e.g. 10m, 1h)
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+        idle_lock: Option<Duration>,
+    },
+    /// Keep the machine awake without locking the screen
+    Awake {
+        /// Stop after this long (e.g. 3h, 45m)
+        #[arg(long = "for", value_name = "DURATION", value_parser = duration::parse_duration)]
+        duration: Option<Duration>,
+        /// Stop at this local time (HH:MM)
+        #[arg(long, value_name = "TIME", value_parser = awake::parse_clock_time)]
+        until: Option<chrono::NaiveTime>,
+        /// Stop once this process exits
+        #[arg(long, value_name = "PID")]
+        while_pid: Option<u32>,
+        /// What to keep awake
+        #[arg(long, value_enum, default_value = "system")]
+        sleep_profile: SleepProfile,
+        /// Keep the display on too (same as --sleep-profile system+display)
+        #[arg(long, conflicts_with = "sleep_profile")]
+        display: bool,
+        /// Run this command and stop when it exits
+        #[arg(last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Lock the screen and run a command, logging its output, until it exits
+    Run {
+        /// Lock the controlling terminal instead of showing a GUI overlay
+        #[arg(long)]
+        tty: bool,
+        /// What to keep awake while the command runs
+        #[arg(long, value_enum, default_value = "system+display+lid")]
+        sleep_profile: SleepProfile,
+        /// Append the command's output to this file (default: a new file under runs/ in the config directory)
+        #[arg(long, value_name = "PATH")]
+        log: Option<PathBuf>,
+        /// Show a desktop notification when the command exits
+        #[arg(long)]
+        notify: bool,
+        /// Show this process (PID or name) on the lock screen; repeat for more (adds to panel.json)
+        #[arg(long = "watch", value_name = "PID|NAME")]
+        watch: Vec<String>,
+        /// Show the last lines of this log file on the lock screen; repeat for more
+        #[arg(long = "tail", value_name = "PATH")]
+        tail: Vec<PathBuf>,
+        /// The command to run
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Send a request to the running daemon
+    Ctl {
+        /// Print the daemon's JSON response instead of a summary
+        #[arg(long)]
+        json: bool,
+        #[command(subcommand)]
+        request: control::Request,
+    },
+    /// Inspect the audit log of lock sessions and unlock attempts
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// List recent lock sessions: how long, failed attempts and how they ended
+    History {
+        /// How many sessions to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Totals over recent lock sessions: time locked per day and week, longest session, failure rate
+    Stats {
+        /// How many days back to count
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+        #[arg(long)]
+        json: bool,
+    },
+    Status {
+        /// Print a machine-readable report: auth scheme, running lock and daemon, sleep prevention, lockout
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Check that no entry has been altered, removed or reordered
+    Verify,
+}
+
+/// Choice of PIN or passphrase for `setup` and `change-pin`.
+#[derive(clap::Args)]
+struct CredentialArgs {
+    /// Use an alphanumeric passphrase instead of a numeric PIN
+    #[arg(long, conflicts_with = "pin")]
+    passphrase: bool,
+    /// Use a numeric PIN (the default; switches back from a passphrase)
+    #[arg(long)]
+    pin: bool,
+    /// Shortest passphrase accepted, in characters (default 12)
+    #[arg(long, value_name = "N", requires = "passphrase")]
+    min_length: Option<usize>,
+    /// Character class the passphrase must contain: lower, upper, digit or symbol; repeat for more
+    #[arg(long = "require", value_enum, value_name = "CLASS", requires = "passphrase")]
+    require: Vec<auth::CharClass>,
+}
+
+impl CredentialArgs {
+    /// The policy asked for, or `None` to keep the current one.
+    fn policy(self) -> Option<auth::CredentialPolicy> {
+        if self.passphrase {
+            let default = auth::CredentialPolicy::passphrase();
+            let min_length = self.min_length.unwrap_or(default.min_length);
+            Some(auth::CredentialPolicy {
+                min_length,
+                max_length: default.max_length.max(min_length),
+                required_classes: self.require,
+                ..default
+            })
+        } else if self.pin {
+            Some(auth::CredentialPolicy::default())
+        } else {
+            None
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Setup => {
-            auth::setup_pin()?;
+        Commands::Setup { credential, totp } => {
+            auth::setup_pin(credential.policy().unwrap_or_default(), totp)?;
+        }
+        Commands::ChangePin { credential } => {
+            auth::change_pin(credential.policy())?;
+        }
+        Commands::Reset => {
+            auth::reset_pin()?;
         }
-        Commands::Lock => {
+        Commands::RecoveryCodes => {
+            auth::new_recovery_codes()?;
+        }
+        Commands::Totp { mode, off: _ } => {
+            auth::configure_totp(mode)?;
+        }
+        Commands::Duress {
+            marker,
+            stop,
+            off,
+            hook,
+        } => {
+            if off {
+                auth::remove_duress()?;
+            } else {
+                auth::configure_duress(hook, marker, stop)?;
+            }
+        }
+        Commands::Lock {
+            tty,
+            sleep_profile,
+            watch,
+            tail,
+        } => {
             if !config_exists()? {
                 println!("No PIN configured. Run 'agent-lock setup' first.");
                 return Ok(());
             }
 
-            println!("Starting screen lock...");
-
-            let mut sleep_preventer = SleepPreventer::new();
-            sleep_preventer.start()?;
+            let mut backend = lock_backend(tty)?;
+            let panel = InfoPanel::default();
+            let _monitor = status_monitor(watch, tail, &panel)?;
+            backend.set_panel(panel);
+            lock(backend.as_mut(), sleep_profile)?;
 
-            #[cfg(target_os = "macos")]
-            {
-                use std::sync::atomic::{AtomicBool, Ordering};
-                use std::sync::Arc;
-
-                let locked = Arc::new(AtomicBool::new(true));
-                let locked_clone = Arc::clone(&locked);
-
-                ctrlc::set_handler(move || {
-                    if !locked_clone.load(Ordering::SeqCst) {
-                        std::process::exit(0);
-                    }
-                }).ok();
-
-                locker::show_lock_screen()?;
-                locked.store(false, Ordering::SeqCst);
+            println!("Screen unlocked. Sleep prevention disabled.");
+        }
+        Commands::Daemon { hotkeys, idle_lock } => {
+            if !config_exists()? {
+                println!("No PIN configured. Run 'agent-lock setup' first.");
+                return Ok(());
             }
 
-            #[cfg(not(target_os = "macos"))]
-            locker::show_lock_screen()?;
-
-            sleep_preventer.stop();
-            println!("Screen unlocked. Sleep prevention disabled.");
+            daemon::run(hotkeys, idle_lock)?;
         }
-        Commands::Daemon => {
+        Commands::Awake {
+            duration,
+            until,
+            while_pid,
+            sleep_profile,
+            display,
+            command,
+        } => {
+            let code = awake::run(awake::AwakeOptions {
+                duration,
+                until,
+                while_pid,
+                command,
+                profile: if display {
+                    SleepProfile::Display
+                } else {
+                    sleep_profile
+                },
+            })?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Run {
+            tty,
+            sleep_profile,
+            log,
+            notify,
+            mut watch,
+            mut tail,
+            command,
+        } => {
             if !config_exists()? {
                 println!("No PIN configured. Run 'agent-lock setup' first.");
                 return Ok(());
             }
 
-            println!("Starting agent-lock daemon...");
-            println!("Press Cmd+Shift+L to lock screen");
-            println!("Press Ctrl+C to quit daemon");
-            println!();
-            println!("Note: If hotkey doesn't work, grant Accessibility permissions:");
-            println!("  System Settings → Privacy & Security → Accessibility");
-            println!();
-
-            use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, hotkey::{Code, Modifiers, HotKey}};
-            use std::sync::atomic::{AtomicBool, Ordering};
-            use std::sync::Arc;
-            use std::process::Command;
-
-            let manager = GlobalHotKeyManager::new().context("Failed to create hotkey manager - may need Accessibility permissions")?;
-
-            // Try Cmd+Shift+L first, fallback to Cmd+Option+L
-            let hotkey = HotKey::new(Some(Modifiers::SUPER | Modifiers::SHIFT), Code::KeyL);
-            let registered_hotkey = match manager.register(hotkey) {
-                Ok(_) => {
-                    println!("✓ Hotkey registered: Cmd+Shift+L");
-                    hotkey
-                }
-                Err(_) => {
-                    // Fallback to Cmd+Option+L
-                    let alt_hotkey = HotKey::new(Some(Modifiers::SUPER | Modifiers::ALT), Code::KeyL);
-                    match manager.register(alt_hotkey) {
-                        Ok(_) => {
-                            println!("✓ Hotkey registered: Cmd+Option+L (fallback)");
-                            alt_hotkey
-                        }
-                        Err(e) => {
-                            println!("✗ Failed to register hotkey: {}", e);
-                            println!("\nTroubleshooting:");
-                            println!("1. Grant Accessibility permissions:");
-                            println!("   System Settings → Privacy & Security → Accessibility");
-                            println!("2. Add Terminal.app (or your terminal) to allowed apps");
-                            println!("3. Restart terminal and try again");
-                            println!("\nNote: Some apps may conflict with global hotkeys");
-                            return Err(e.into());
-                        }
-                    }
-                }
+            // Set up the lock screen before starting the command, so a missing
+            // display does not leave the command running unattended.
+            let mut backend = lock_backend(tty)?;
+            let log = match log {
+                Some(log) => log,
+                None => run::default_log_path()?,
             };
+            let panel = InfoPanel::default();
+            let agent = run::AgentRun::start(&command, &log, panel.clone(), notify)?;
+            println!("Started {} (output in {})", command.join(" "), log.display());
 
-            let running = Arc::new(AtomicBool::new(true));
-            let r = Arc::clone(&running);
+            watch.push(agent.pid().to_string());
+            tail.push(log);
+            let locked = status_monitor(watch, tail, &panel).and_then(|monitor| {
+                backend.set_panel(panel);
+                lock(backend.as_mut(), sleep_profile)?;
+                drop(monitor);
+                Ok(())
+            });
+            if let Err(e) = locked {
+                // Without the lock nothing watches the command, so stop it
+                // rather than leave it running unattended.
+                match agent.abort() {
+                    Ok(status) => eprintln!("Stopped {}: {}", command[0], status),
+                    Err(abort) => eprintln!("Failed to stop {}: {:#}", command[0], abort),
+                }
+                return Err(e);
+            }
+            println!("Screen unlocked.");
 
-            ctrlc::set_handler(move || {
-                r.store(false, Ordering::SeqCst);
-            })
-            .context("Error setting Ctrl-C handler")?;
-
-            let receiver = GlobalHotKeyEvent::receiver();
-            let exe_path = std::env::current_exe()?;
-
-            while running.load(Ordering::SeqCst) {
-                if let Ok(_event) = receiver.try_recv() {
-                    println!("Hotkey triggered - locking screen...");
-
-                    let child = Command::new(&exe_path)
-                        .arg("lock")
-                        .spawn();
-
-                    match child {
-                        Ok(mut process) => {
-                            let _ = process.wait();
-                            println!("Screen unlocked");
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to spawn lock process: {}", e);
-                        }
+            let status = agent.finish(sleep_profile)?;
+            println!("{} finished: {}", command[0], status);
+            let code = status.code().unwrap_or(1);
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Ctl { json, request } => {
+            let response = control::send(request)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&response)?);
+            } else {
+                println!("{}", response.message);
+                if let Some(status) = &response.status {
+                    println!("Daemon PID: {}", status.pid);
+                    if let Some(pid) = status.lock_pid {
+                        println!("Lock PID: {}", pid);
+                    }
+                    println!("Hotkey: {}", status.hotkey);
+                    if let Some(secs) = status.idle_lock_secs {
+                        println!("Idle lock: {}s", secs);
                     }
                 }
-                std::thread::sleep(std::time::Duration::from_millis(100));
             }
-
-            manager.unregister(registered_hotkey)?;
-            println!("\nDaemon stopped");
+            if !response.ok {
+                std::process::exit(1);
+            }
+        }
+        Commands::Audit {
+            command: AuditCommand::Verify,
+        } => {
+            let path = audit::get_audit_path()?;
+            let count = audit::verify(&path)?;
+            println!("✓ Audit log intact: {} entries in {}", count, path.display());
+        }
+        Commands::History { limit, json } => {
+            let records = lock_history()?;
+            let recent = &records[records.len().saturating_sub(limit)..];
+            if json {
+                println!("{}", serde_json::to_string_pretty(recent)?);
+            } else {
+                history::print_history(recent);
+            }
+        }
+        Commands::Stats { days, json } => {
+            let since = auth::unix_now().saturating_sub(days * 24 * 3600);
+            let stats = history::Stats::compute(&lock_history()?, since, &chrono::Local);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                stats.print();
+            }
+        }
+        Commands::Status { json: true } => {
+            let report = status::StatusReport::collect()?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
         }
-        Commands::Status => {
+        Commands::Status { json: false } => {
             if config_exists()? {
                 println!("✓ PIN is configured");
                 println!("✓ Ready to lock screen");
                 println!("\nUsage:");
                 println!("  agent-lock lock        - Lock screen immediately");
-                println!("  agent-lock daemon      - Run in background (Cmd+Shift+L to lock)");
+                println!("  agent-lock lock --tty  - Lock this terminal (SSH, console)");
+                let hotkey = hotkey::get_daemon_config_path()
+                    .and_then(|path| hotkey::DaemonConfig::load(&path))
+                    .ok()
+                    .and_then(|config| config.hotkeys.into_iter().next());
+                match hotkey {
+                    Some(hotkey) => println!(
+                        "  agent-lock daemon      - Run in background ({} to lock)",
+                        hotkey
+                    ),
+                    None => {
+                        println!("  agent-lock daemon      - Run in background (hotkey to lock)")
+                    }
+                }
+                println!("  agent-lock awake --for 3h - Keep awake without locking");
+                println!("  agent-lock run -- <command> - Lock while a command runs, logging its output");
+                println!("  agent-lock daemon --idle-lock 10m - Also lock after 10 minutes idle");
+                println!("  agent-lock ctl lock    - Ask the running daemon to lock");
+                println!("  agent-lock status --json - Report lock, daemon and sleep state");
+                println!("  agent-lock audit verify - Check the audit log for tampering");
+                println!("  agent-lock history     - List recent lock sessions");
+                println!("  agent-lock stats       - Time locked per day and week");
             } else {
                 println!("✗ No PIN configured");
                 println!("Run 'agent-lock setup' to configure a PIN");
@@ -166,7 +444,180 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Lock sessions recorded in the audit log, oldest first.
+fn lock_history() -> Result<Vec<history::LockRecord>> {
+    let entries = audit::read(&audit::get_audit_path()?)?;
+    let running = session::running(&session::get_sessions_dir()?);
+    Ok(history::sessions(
+        &entries,
+        auth::unix_now(),
+        |pid, started| history::lock_running(&running, pid, started),
+    ))
+}
+
+/// Keep the status section of `panel` up to date with `panel.json` plus the
+/// processes in `watch` and logs in `tail`.
+fn status_monitor(
+    watch: Vec<String>,
+    tail: Vec<PathBuf>,
+    panel: &InfoPanel,
+) -> Result<StatusMonitor> {
+    let mut config = PanelConfig::load(&monitor::get_panel_config_path()?)?;
+    config.processes.extend(watch);
+    config.logs.extend(tail);
+    Ok(StatusMonitor::start(config, panel.clone()))
+}
+
+fn lock_backend(tty: bool) -> Result<Box<dyn LockBackend>> {
+    if tty {
+        locker::tty_backend()
+    } else {
+        locker::native_backend()
+    }
+}
+
+/// Lock with `backend` until the PIN is entered, keeping the machine awake
+/// with `profile` meanwhile.
+fn lock(backend: &mut dyn LockBackend, profile: SleepProfile) -> Result<()> {
+    println!("Starting screen lock...");
+    let started = auth::unix_now();
+    audit::record(AuditEvent::LockStarted);
+    let mut matched = None;
+    let verify = |pin: &str| {
+        matched = auth::verify_pin_internal(pin);
+        if matched.is_none() {
+            audit::record(AuditEvent::UnlockFailed);
+        }
+        matched.is_some()
+    };
+    let sleep_preventer = SleepPreventer::new(profile);
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let locked = Arc::new(AtomicBool::new(true));
+        let locked_clone = Arc::clone(&locked);
+
+        ctrlc::set_handler(move || {
+            if !locked_clone.load(Ordering::SeqCst) {
+                std::process::exit(0);
+            }
+        }).ok();
+
+        lock_session(backend, sleep_preventer, Some(SessionKind::Lock), verify)?;
+        locked.store(false, Ordering::SeqCst);
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    lock_session(backend, sleep_preventer, Some(SessionKind::Lock), verify)?;
+
+    // A duress unlock is logged like a normal one so the log does not give
+    // it away.
+    let method = match matched {
+        Some(auth::Credential::RecoveryCode) => auth::Credential::RecoveryCode,
+        _ => auth::Credential::Primary,
+    };
+    audit::record(AuditEvent::Unlocked {
+        method,
+        duration_secs: auth::unix_now().saturating_sub(started),
+    });
+    if matched == Some(auth::Credential::Duress) {
+        if let Some(duress) = auth::AuthConfig::load(&auth::get_config_path()?)?.duress {
+            duress::trigger(&duress);
+        }
+    }
+    Ok(())
+}
+
+/// Keep the machine awake with `sleep_preventer` while `backend` holds the
+/// screen locked until `verify` accepts a PIN. With `session`, the lock is
+/// recorded for `agent-lock status`.
+fn lock_session(
+    backend: &mut dyn LockBackend,
+    mut sleep_preventer: SleepPreventer,
+    session: Option<SessionKind>,
+    verify: impl FnMut(&str) -> bool,
+) -> Result<()> {
+    sleep_preventer.start()?;
+    if let SleepStatus::Unavailable(reason) = sleep_preventer.status() {
+        eprintln!("Warning: the machine may sleep while locked ({})", reason);
+    }
+    let session = session.map(|kind| Session::begin(kind, &sleep_preventer));
+
+    locker::run(backend, verify)?;
+
+    drop(session);
+    sleep_preventer.stop();
+    Ok(())
+}
+
 fn config_exists() -> Result<bool> {
     let path = auth::get_config_path()?;
     Ok(path.exists())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use locker::headless::{HeadlessBackend, HeadlessEvent};
+
+    #[test]
+    fn test_lock_session_wrong_then_right_pin() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-main-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.json");
+        let lockout_path = dir.join("lockout.json");
+        let kdf = auth::KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        auth::AuthConfig::with_params("1234", kdf)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        let mut failures = Vec::new();
+        let mut backend = HeadlessBackend::new(["0000", "1234"]);
+        let preventer = SleepPreventer::inert(SleepProfile::System);
+        lock_session(&mut backend, preventer, None, |pin| {
+            let now = auth::unix_now();
+            let matched = auth::verify_pin_with_lockout(&path, &lockout_path, pin, now);
+            let state = auth::LockoutState::load(&lockout_path).unwrap();
+            failures.push(state.failed_attempts);
+            matched == Some(auth::Credential::Primary)
+        })
+        .unwrap();
+
+        // The wrong PIN counted as a failed attempt, the right one cleared it.
+        assert_eq!(failures, vec![1, 0]);
+        assert!(auth::LockoutState::load(&lockout_path)
+            .unwrap()
+            .last_unlock
+            .is_some());
+        let events = backend.events();
+        assert!(matches!(events[2], HeadlessEvent::Failed(_)));
+        assert_eq!(events[events.len() - 2..], [HeadlessEvent::Succeeded, HeadlessEvent::TornDown]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_shows_exit_status_on_lock_screen() {
+        let log = std::env::temp_dir().join(format!("agent-lock-main-run-{}.log", std::process::id()));
+        let panel = InfoPanel::default();
+        let command = ["sh", "-c", "exit 0"].map(String::from);
+        let agent = run::AgentRun::start(&command, &log, panel.clone(), false).unwrap();
+
+        let mut backend = HeadlessBackend::new(["1234"]);
+        backend.set_panel(panel);
+        let preventer = SleepPreventer::inert(SleepProfile::System);
+        lock_session(&mut backend, preventer, None, |pin| pin == "1234").unwrap();
+
+        assert_eq!(agent.finish(SleepProfile::System).unwrap().code(), Some(0));
+        assert!(backend.panel_lines()[0].starts_with("sh -c exit 0 exited with code 0 at"));
+        std::fs::remove_file(&log).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use crate::duration::format_duration;
+use crate::locker::InfoPanel;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use sysinfo::{Pid, ProcessRefreshKind, System};
+
+/// Longest panel line; longer log lines are cut so they fit on screen.
+const MAX_LINE_CHARS: usize = 100;
+/// How far back from the end of a log file to look for its last lines.
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// What the lock screen status panel shows, read from `panel.json` next to
+/// `auth.json`. `lock` and `run` add their `--watch` and `--tail` arguments.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PanelConfig {
+    /// Processes to show, each a PID or a process name.
+    pub processes: Vec<String>,
+    /// Log files to show the last lines of.
+    pub logs: Vec<PathBuf>,
+    /// How many lines of each log to show.
+    pub log_lines: usize,
+    /// Seconds between refreshes.
+    pub refresh_secs: u64,
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self {
+            processes: Vec::new(),
+            logs: Vec::new(),
+            log_lines: 3,
+            refresh_secs: 2,
+        }
+    }
+}
+
+impl PanelConfig {
+    /// Load the config at `path`, treating a missing file as the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path).context("Failed to read panel config")?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Invalid panel config {}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty() && self.logs.is_empty()
+    }
+}
+
+pub fn get_panel_config_path() -> Result<PathBuf> {
+    Ok(crate::auth::get_config_path()?.with_file_name("panel.json"))
+}
+
+/// Background thread that refreshes the "status" section of an
+/// [`InfoPanel`] until it is stopped or dropped.
+pub struct StatusMonitor {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatusMonitor {
+    /// Start refreshing `panel` from `config`. With nothing to watch no
+    /// thread is started.
+    pub fn start(config: PanelConfig, panel: InfoPanel) -> Self {
+        if config.is_empty() {
+            return Self {
+                stop: None,
+                thread: None,
+            };
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let refresh = Duration::from_secs(config.refresh_secs.max(1));
+        let thread = thread::spawn(move || {
+            let mut system = System::new();
+            loop {
+                panel.set_section("status", collect(&mut system, &config));
+                match stopped.recv_timeout(refresh) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for StatusMonitor {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Panel lines for every watched process and log file in `config`.
+fn collect(system: &mut System, config: &PanelConfig) -> Vec<String> {
+    let mut lines = Vec::new();
+    if !config.processes.is_empty() {
+        system.refresh_processes_specifics(ProcessRefreshKind::new().with_cpu().with_memory());
+        for target in &config.processes {
+            lines.extend(process_lines(system, target));
+        }
+    }
+    for log in &config.logs {
+        let name = log
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| log.display().to_string());
+        match tail_lines(log, config.log_lines) {
+            Ok(tail) => {
+                lines.push(format!("{}:", name));
+                lines.extend(tail.iter().map(|line| format!("  {}", line)));
+            }
+            Err(_) => lines.push(format!("{}: not readable", name)),
+        }
+    }
+    lines
+}
+
+fn process_lines(system: &System, target: &str) -> Vec<String> {
+    let processes: Vec<_> = match target.parse::<u32>() {
+        Ok(pid) => system.process(Pid::from_u32(pid)).into_iter().collect(),
+        Err(_) => {
+            let mut matching: Vec<_> = system
+                .processes()
+                .values()
+                .filter(|process| process.name() == target)
+                .collect();
+            matching.sort_by_key(|process| process.pid());
+            matching
+        }
+    };
+    if processes.is_empty() {
+        return vec![format!("{}: not running", sanitize(target))];
+    }
+    processes
+        .into_iter()
+        .map(|process| {
+            format!(
+                "{} ({}): up {}, CPU {:.0}%, {}",
+                sanitize(process.name()),
+                process.pid(),
+                format_duration(process.run_time()),
+                process.cpu_usage(),
+                format_memory(process.memory())
+            )
+        })
+        .collect()
+}
+
+/// The last `count` lines of the file at `path`, made safe to draw.
+fn tail_lines(path: &Path, count: usize) -> Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let text = String::from_utf8_lossy(&bytes);
+    let mut lines: Vec<&str> = text.lines().collect();
+    // The first line is probably cut off when reading from the middle.
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines[skip..].iter().map(|line| sanitize(line)).collect())
+}
+
+/// Drop control characters, so a log cannot move the cursor or send escape
+/// sequences to the terminal lock screen, and cut long lines.
+fn sanitize(text: &str) -> String {
+    let clean: String = text
+        .chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+    if clean.chars().count() > MAX_LINE_CHARS {
+        let cut: String = clean.chars().take(MAX_LINE_CHARS - 3).collect();
+        format!("{}...", cut)
+    } else {
+        clean
+    }
+}
+
+fn format_memory(bytes: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    if bytes >= 1024 * MIB {
+        format!("{:.1} GB", bytes as f64 / (1024 * MIB) as f64)
+    } else {
+        format!("{} MB", bytes / MIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_lines_sanitized() {
+        let path = std::env::temp_dir().join(format!("agent-lock-tail-{}.log", std::process::id()));
+        fs::write(&path, "one\ntwo\n\x1b[2Jthree\tdone\nfour\n").unwrap();
+
+        assert_eq!(tail_lines(&path, 2).unwrap(), vec!["[2Jthree done", "four"]);
+        assert_eq!(tail_lines(&path, 10).unwrap().len(), 4);
+        fs::remove_file(&path).unwrap();
+
+        assert!(tail_lines(&path, 2).is_err());
+        assert_eq!(sanitize(&"x".repeat(150)).len(), MAX_LINE_CHARS);
+    }
+
+    #[test]
+    fn test_collect_reports_processes_and_logs() {
+        let config = PanelConfig {
+            processes: vec![std::process::id().to_string(), "no-such-agent".to_string()],
+            logs: vec![PathBuf::from("/nonexistent/agent.log")],
+            ..PanelConfig::default()
+        };
+        let lines = collect(&mut System::new(), &config);
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(&format!("({}): up ", std::process::id())));
+        assert!(lines[0].contains("MB"));
+        assert_eq!(lines[1], "no-such-agent: not running");
+        assert_eq!(lines[2], "agent.log: not readable");
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_memory(340 * 1024 * 1024), "340 MB");
+        assert_eq!(format_memory(3 * 1024 * 1024 * 1024 / 2), "1.5 GB");
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+
+/// Show a desktop notification with the platform's own tool: `osascript` on
+/// macOS, `notify-send` on Linux and a PowerShell balloon tip on Windows.
+pub fn send(title: &str, body: &str) -> Result<()> {
+    let status = notifier(title, body)
+        .status()
+        .context("Failed to start the notification tool")?;
+    if !status.success() {
+        bail!("Notification tool exited with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn notifier(title: &str, body: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quoted(body),
+        quoted(title)
+    ));
+    command
+}
+
+#[cfg(target_os = "linux")]
+fn notifier(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name", "agent-lock", title, body]);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn notifier(title: &str, body: &str) -> Command {
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; \
+         $icon = New-Object System.Windows.Forms.NotifyIcon; \
+         $icon.Icon = [System.Drawing.SystemIcons]::Information; \
+         $icon.Visible = $true; \
+         $icon.ShowBalloonTip(6000, {}, {}, 'Info'); \
+         Start-Sleep -Seconds 6; \
+         $icon.Dispose()",
+        quoted(title),
+        quoted(body)
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    command
+}
+
+/// An AppleScript string literal.
+#[cfg(target_os = "macos")]
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A PowerShell single-quoted string literal.
+#[cfg(target_os = "windows")]
+fn quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
@@ -0,0 +1,217 @@
+use crate::locker::InfoPanel;
+use crate::notify;
+use crate::session::{Session, SessionKind};
+use crate::sleep_prevention::{SleepPreventer, SleepProfile, SleepStatus};
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use sysinfo::{Pid, System};
+
+/// A command started by `agent-lock run`. Its stdout and stderr go to a log
+/// file, and a watcher thread records how it ended in the log and on the
+/// lock screen panel.
+pub struct AgentRun {
+    label: String,
+    pid: u32,
+    watcher: JoinHandle<Result<ExitStatus>>,
+}
+
+impl AgentRun {
+    /// Start `command` with its output appended to `log`, showing its state
+    /// on `panel`. With `notify`, a desktop notification fires when it exits.
+    pub fn start(command: &[String], log: &Path, panel: InfoPanel, notify: bool) -> Result<Self> {
+        let Some((program, args)) = command.split_first() else {
+            bail!("No command given. Usage: agent-lock run -- <command>");
+        };
+        let label = command.join(" ");
+
+        if let Some(dir) = log.parent() {
+            fs::create_dir_all(dir).context("Failed to create log directory")?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .with_context(|| format!("Failed to open log file {}", log.display()))?;
+        writeln!(
+            file,
+            "# agent-lock run: {} (started {})",
+            label,
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        )?;
+
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(file.try_clone()?)
+            .stderr(file.try_clone()?)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", program))?;
+
+        let pid = child.id();
+        let log_line = format!("Log: {}", log.display());
+        panel.set_section("run", vec![format!("Running: {}", label), log_line.clone()]);
+
+        let watcher = {
+            let label = label.clone();
+            thread::spawn(move || watch(child, file, &label, &log_line, &panel, notify))
+        };
+        Ok(Self {
+            label,
+            pid,
+            watcher,
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Wait for the command to exit. If it is still running, the machine is
+    /// kept awake with `profile` until it does.
+    pub fn finish(self, profile: SleepProfile) -> Result<ExitStatus> {
+        if self.watcher.is_finished() {
+            return self.wait();
+        }
+
+        println!("Waiting for {} to finish...", self.label);
+        let mut sleep_preventer = SleepPreventer::new(profile);
+        sleep_preventer.start()?;
+        if let SleepStatus::Unavailable(reason) = sleep_preventer.status() {
+            eprintln!("Warning: the machine may sleep while waiting ({})", reason);
+        }
+        let session = Session::begin(SessionKind::Run, &sleep_preventer);
+        let status = self.wait();
+        drop(session);
+        sleep_preventer.stop();
+        status
+    }
+
+    /// Kill the command and wait for it, for when the lock could not be
+    /// started: nothing would watch it or keep the machine awake for it.
+    pub fn abort(self) -> Result<ExitStatus> {
+        // The watcher has not reaped the command yet, so its PID cannot have
+        // been reused.
+        let pid = Pid::from_u32(self.pid);
+        let mut system = System::new();
+        if system.refresh_process(pid) {
+            if let Some(process) = system.process(pid) {
+                process.kill();
+            }
+        }
+        self.wait()
+    }
+
+    fn wait(self) -> Result<ExitStatus> {
+        self.watcher
+            .join()
+            .map_err(|_| anyhow::anyhow!("Command watcher thread panicked"))?
+    }
+}
+
+fn watch(
+    mut child: Child,
+    mut log: File,
+    label: &str,
+    log_line: &str,
+    panel: &InfoPanel,
+    notify: bool,
+) -> Result<ExitStatus> {
+    let status = child.wait()?;
+    let outcome = describe_exit(status);
+    let finished = Local::now();
+
+    // The exit status is already known; a full disk must not lose it.
+    let _ = writeln!(
+        log,
+        "# {} {} (finished {})",
+        label,
+        outcome,
+        finished.format("%Y-%m-%d %H:%M:%S")
+    );
+    panel.set_section(
+        "run",
+        vec![
+            format!("{} {} at {}", label, outcome, finished.format("%H:%M")),
+            log_line.to_string(),
+        ],
+    );
+
+    if notify {
+        if let Err(e) = notify::send("agent-lock", &format!("{} {}", label, outcome)) {
+            let _ = writeln!(log, "# notification failed: {:#}", e);
+        }
+    }
+    Ok(status)
+}
+
+fn describe_exit(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exited with code {}", code),
+        None => "was killed by a signal".to_string(),
+    }
+}
+
+/// A new log file under `runs/` next to `auth.json`, named by start time.
+pub fn default_log_path() -> Result<PathBuf> {
+    let name = format!("{}.log", Local::now().format("%Y%m%d-%H%M%S"));
+    Ok(crate::auth::get_config_path()?
+        .with_file_name("runs")
+        .join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_logs_output_and_exit_status() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-run-{}", std::process::id()));
+        let log = dir.join("runs").join("agent.log");
+        let panel = InfoPanel::default();
+        let command = ["sh", "-c", "echo working; echo oops >&2; exit 3"].map(String::from);
+
+        let run = AgentRun::start(&command, &log, panel.clone(), false).unwrap();
+        let status = run.wait().unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let text = fs::read_to_string(&log).unwrap();
+        assert!(text.starts_with("# agent-lock run: sh -c"));
+        assert!(text.contains("working\n"));
+        assert!(text.contains("oops\n"));
+        assert!(text.contains("exited with code 3 (finished"));
+
+        let lines = panel.lines();
+        assert!(lines[0].contains("exited with code 3 at"), "{:?}", lines);
+        assert_eq!(lines[1], format!("Log: {}", log.display()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_abort_kills_the_command() {
+        let log = std::env::temp_dir().join(format!("agent-lock-abort-{}.log", std::process::id()));
+        let command = ["sleep", "30"].map(String::from);
+        let run = AgentRun::start(&command, &log, InfoPanel::default(), false).unwrap();
+        let pid = run.pid();
+
+        let status = run.abort().unwrap();
+        assert_eq!(status.code(), None);
+        assert!(!crate::process::alive(pid));
+        assert!(fs::read_to_string(&log)
+            .unwrap()
+            .contains("sleep 30 was killed by a signal"));
+        fs::remove_file(&log).unwrap();
+    }
+
+    #[test]
+    fn test_run_needs_a_command() {
+        let log = std::env::temp_dir().join("agent-lock-empty-run.log");
+        assert!(AgentRun::start(&[], &log, InfoPanel::default(), false).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::sleep_prevention::{SleepPreventer, SleepStatus};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often a running session checks whether its sleep prevention changed.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// What is holding the machine awake.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    /// The screen is locked, by `lock`, `run` or the daemon.
+    Lock,
+    /// `run` was unlocked and is waiting for its command.
+    Run,
+    /// `awake` without a lock screen.
+    Awake,
+}
+
+/// A running lock, run or awake session, kept in `sessions/<pid>.json` next
+/// to `auth.json` so `agent-lock status` can report on it from another
+/// process. Each process has its own record, so an `awake` next to a `lock`
+/// does not hide it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub kind: SessionKind,
+    pub pid: u32,
+    /// Unix timestamp (seconds) the session started.
+    pub started: u64,
+    pub sleep_profile: String,
+    /// `active`, `starting`, `retrying` or `unavailable`, kept up to date
+    /// while the session runs.
+    pub sleep_state: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep_detail: Option<String>,
+}
+
+impl SessionRecord {
+    fn new(kind: SessionKind, preventer: &SleepPreventer) -> Self {
+        let mut record = Self {
+            kind,
+            pid: std::process::id(),
+            started: crate::auth::unix_now(),
+            sleep_profile: preventer.profile().name().to_string(),
+            sleep_state: String::new(),
+            sleep_detail: None,
+        };
+        record.set_sleep_status(&preventer.status());
+        record
+    }
+
+    /// Record `status`, returning whether anything changed.
+    fn set_sleep_status(&mut self, status: &SleepStatus) -> bool {
+        let (state, detail) = match status {
+            SleepStatus::Active => ("active", None),
+            // caffeinate is started by a supervisor thread that may not have
+            // got to it yet.
+            SleepStatus::Stopped => ("starting", None),
+            SleepStatus::Retrying { error, .. } => ("retrying", Some(error.clone())),
+            SleepStatus::Unavailable(reason) => ("unavailable", Some(reason.clone())),
+        };
+        if self.sleep_state == state && self.sleep_detail == detail {
+            return false;
+        }
+        self.sleep_state = state.to_string();
+        self.sleep_detail = detail;
+        true
+    }
+
+    /// Whether the machine is actually being kept awake.
+    pub fn sleep_active(&self) -> bool {
+        self.sleep_state == "active"
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).context("Failed to save session record")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).context("Failed to read session record")?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+pub fn get_sessions_dir() -> Result<PathBuf> {
+    Ok(crate::auth::get_config_path()?.with_file_name("sessions"))
+}
+
+fn record_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.json", pid))
+}
+
+/// The sessions recorded in `dir` whose processes are still running, oldest
+/// first. Records left behind by a crash are ignored.
+pub fn running(dir: &Path) -> Vec<SessionRecord> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut records: Vec<SessionRecord> = entries
+        .flatten()
+        .filter_map(|entry| SessionRecord::load(&entry.path()).ok())
+        .filter(|record| crate::process::alive(record.pid))
+        .collect();
+    records.sort_by_key(|record| (record.started, record.pid));
+    records
+}
+
+/// Records a session for as long as it is alive, rewriting the record when
+/// its sleep prevention changes, and removes the record when dropped.
+pub struct Session {
+    path: Option<PathBuf>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    /// Record that this process started a `kind` session kept awake by
+    /// `preventer`. Failing to write the record only costs `status` output,
+    /// so it is reported and otherwise ignored.
+    pub fn begin(kind: SessionKind, preventer: &SleepPreventer) -> Self {
+        let record = SessionRecord::new(kind, preventer);
+        let path = get_sessions_dir().and_then(|dir| {
+            fs::create_dir_all(&dir)?;
+            let path = record_path(&dir, record.pid);
+            record.save(&path)?;
+            Ok(path)
+        });
+        match path {
+            Ok(path) => {
+                let (stop, stopped) = mpsc::channel();
+                let thread = {
+                    let path = path.clone();
+                    let status = preventer.status_handle();
+                    thread::spawn(move || refresh(record, &path, &status, stopped))
+                };
+                Self {
+                    path: Some(path),
+                    stop: Some(stop),
+                    thread: Some(thread),
+                }
+            }
+            Err(e) => {
+                eprintln!("Warning: failed to record session: {:#}", e);
+                Self {
+                    path: None,
+                    stop: None,
+                    thread: None,
+                }
+            }
+        }
+    }
+}
+
+/// Rewrite `record` at `path` whenever `status` changes, until told to stop.
+fn refresh(
+    mut record: SessionRecord,
+    path: &Path,
+    status: &Mutex<SleepStatus>,
+    stopped: mpsc::Receiver<()>,
+) {
+    loop {
+        match stopped.recv_timeout(REFRESH) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+        let current = status.lock().unwrap().clone();
+        if record.set_sleep_status(&current) {
+            let _ = record.save(path);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sleep_prevention::SleepProfile;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_session_records_per_process() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-sessions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let record = SessionRecord::new(SessionKind::Run, &SleepPreventer::new(SleepProfile::Lid));
+        assert_eq!(record.sleep_profile, "system+lid");
+        assert_eq!(record.sleep_state, "starting");
+        assert!(!record.sleep_active());
+
+        let path = record_path(&dir, record.pid);
+        record.save(&path).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains(r#""kind": "run""#));
+
+        // An older session next to it is listed too; a record whose process
+        // is gone is stale.
+        let other = SessionRecord {
+            kind: SessionKind::Lock,
+            started: record.started - 10,
+            ..record.clone()
+        };
+        other.save(&dir.join("other.json")).unwrap();
+        let stale = SessionRecord {
+            pid: u32::MAX - 1,
+            ..record.clone()
+        };
+        stale.save(&record_path(&dir, stale.pid)).unwrap();
+        assert_eq!(running(&dir), vec![other, record]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(running(&dir), Vec::new());
+    }
+
+    #[test]
+    fn test_refresh_rewrites_changed_status() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-refresh-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.json");
+        let record = SessionRecord::new(
+            SessionKind::Lock,
+            &SleepPreventer::new(SleepProfile::System),
+        );
+        record.save(&path).unwrap();
+
+        let status = Arc::new(Mutex::new(SleepStatus::Stopped));
+        let (stop, stopped) = mpsc::channel();
+        let thread = {
+            let path = path.clone();
+            let status = Arc::clone(&status);
+            thread::spawn(move || refresh(record, &path, &status, stopped))
+        };
+        *status.lock().unwrap() = SleepStatus::Retrying {
+            attempts: 2,
+            error: "exited (1)".to_string(),
+        };
+        thread::sleep(REFRESH * 2);
+        drop(stop);
+        thread.join().unwrap();
+
+        let saved = SessionRecord::load(&path).unwrap();
+        assert_eq!(saved.sleep_state, "retrying");
+        assert_eq!(saved.sleep_detail.as_deref(), Some("exited (1)"));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
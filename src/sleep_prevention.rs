@@ -1,24 +1,109 @@
 use anyhow::Result;
+#[cfg(any(target_os = "macos", all(test, unix)))]
+use std::path::{Path, PathBuf};
+#[cfg(any(target_os = "macos", all(test, unix)))]
+use std::process::Command;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+#[cfg(any(target_os = "macos", all(test, unix)))]
 use std::thread;
-use std::time::Duration;
+#[cfg(any(target_os = "macos", all(test, unix)))]
+use std::time::{Duration, Instant};
+
+/// What to keep awake. Every profile keeps the system from sleeping;
+/// the display and the lid switch are opt-in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SleepProfile {
+    /// Keep the system running; the display may turn off.
+    #[value(name = "system")]
+    System,
+    /// Keep the system running and the display on.
+    #[value(name = "system+display")]
+    Display,
+    /// Keep the system running when the lid is closed (Linux only; macOS and
+    /// Windows leave the lid to their power settings).
+    #[value(name = "system+lid")]
+    Lid,
+    /// Both of the above: the display on, and running with the lid closed.
+    #[value(name = "system+display+lid")]
+    DisplayLid,
+}
+
+impl SleepProfile {
+    /// The name used on the command line, e.g. `system+display`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SleepProfile::System => "system",
+            SleepProfile::Display => "system+display",
+            SleepProfile::Lid => "system+lid",
+            SleepProfile::DisplayLid => "system+display+lid",
+        }
+    }
+
+    fn keeps_display(self) -> bool {
+        matches!(self, SleepProfile::Display | SleepProfile::DisplayLid)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn holds_lid(self) -> bool {
+        matches!(self, SleepProfile::Lid | SleepProfile::DisplayLid)
+    }
+
+    /// The logind inhibitor lock types covering this profile.
+    #[cfg(target_os = "linux")]
+    fn logind_what(self) -> &'static str {
+        match (self.keeps_display(), self.holds_lid()) {
+            (false, false) => "sleep",
+            (true, false) => "sleep:idle",
+            (false, true) => "sleep:handle-lid-switch",
+            (true, true) => "sleep:idle:handle-lid-switch",
+        }
+    }
+}
+
+/// Health of sleep prevention, as reported by [`SleepPreventer::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SleepStatus {
+    Stopped,
+    Active,
+    /// The helper process died or failed to start; another attempt is
+    /// scheduled.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Retrying {
+        attempts: u32,
+        error: String,
+    },
+    /// Sleep prevention is not available on this system.
+    Unavailable(String),
+}
 
 pub struct SleepPreventer {
     running: Arc<AtomicBool>,
+    status: Arc<Mutex<SleepStatus>>,
+    profile: SleepProfile,
     #[cfg(target_os = "macos")]
     handle: Option<thread::JoinHandle<()>>,
+    /// The logind inhibitor lock; sleep is blocked until this fd is closed.
+    #[cfg(target_os = "linux")]
+    inhibitor: Option<zbus::zvariant::OwnedFd>,
 }
 
 impl SleepPreventer {
-    pub fn new() -> Self {
+    pub fn new(profile: SleepProfile) -> Self {
         Self {
             running: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new(SleepStatus::Stopped)),
+            profile,
             #[cfg(target_os = "macos")]
             handle: None,
+            #[cfg(target_os = "linux")]
+            inhibitor: None,
         }
     }
 
+    /// Keep `caffeinate` running until [`stop`](Self::stop), restarting it
+    /// with backoff if it dies. A `caffeinate` left behind by a crashed run
+    /// is killed first.
     #[cfg(target_os = "macos")]
     pub fn start(&mut self) -> Result<()> {
         if self.running.load(Ordering::SeqCst) {
@@ -27,28 +112,18 @@ impl SleepPreventer {
 
         self.running.store(true, Ordering::SeqCst);
         let running = Arc::clone(&self.running);
+        let status = Arc::clone(&self.status);
+        if self.profile == SleepProfile::Lid {
+            eprintln!("Note: closing the lid still puts a Mac to sleep");
+        }
+        let args: &[&str] = if self.profile.keeps_display() {
+            &["-d", "-i", "-s"]
+        } else {
+            &["-i", "-s"]
+        };
+        let supervisor = Supervisor::new("caffeinate", args, pid_dir());
 
-        let handle = thread::spawn(move || {
-            use std::process::Command;
-
-            let child = Command::new("caffeinate")
-                .arg("-d")
-                .arg("-i")
-                .arg("-s")
-                .spawn();
-
-            match child {
-                Ok(mut process) => {
-                    while running.load(Ordering::SeqCst) {
-                        thread::sleep(Duration::from_secs(1));
-                    }
-                    let _ = process.kill();
-                }
-                Err(e) => {
-                    eprintln!("Failed to start caffeinate: {}", e);
-                }
-            }
-        });
+        let handle = thread::spawn(move || supervisor.run(&running, &status));
 
         self.handle = Some(handle);
         Ok(())
@@ -64,14 +139,65 @@ impl SleepPreventer {
             return Ok(());
         }
 
-        unsafe {
-            SetThreadExecutionState(ES_CONTINUOUS | ES_DISPLAY_REQUIRED | ES_SYSTEM_REQUIRED);
+        if self.profile == SleepProfile::Lid {
+            eprintln!("Note: closing the lid follows the Windows power plan");
         }
+        let mut flags = ES_CONTINUOUS | ES_SYSTEM_REQUIRED;
+        if self.profile.keeps_display() {
+            flags |= ES_DISPLAY_REQUIRED;
+        }
+        let state = unsafe { SetThreadExecutionState(flags) };
+        *self.status.lock().unwrap() = if state.0 == 0 {
+            SleepStatus::Unavailable("SetThreadExecutionState failed".to_string())
+        } else {
+            SleepStatus::Active
+        };
 
         self.running.store(true, Ordering::SeqCst);
         Ok(())
     }
 
+    /// Take a logind inhibitor lock. Without a system bus or logind the lock
+    /// still works, but the machine follows its own power settings and
+    /// [`status`](Self::status) reports the problem.
+    #[cfg(target_os = "linux")]
+    pub fn start(&mut self) -> Result<()> {
+        if self.running.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let inhibit = zbus::blocking::Connection::system()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| self.inhibit(&conn));
+        *self.status.lock().unwrap() = match inhibit {
+            Ok(()) => SleepStatus::Active,
+            Err(e) => SleepStatus::Unavailable(format!("logind inhibitor: {:#}", e)),
+        };
+        self.running.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Ask logind on `conn` to block suspend, plus idle actions or the lid
+    /// switch depending on the profile, for as long as the returned fd stays
+    /// open.
+    #[cfg(target_os = "linux")]
+    fn inhibit(&mut self, conn: &zbus::blocking::Connection) -> Result<()> {
+        let reply = conn.call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1",
+            Some("org.freedesktop.login1.Manager"),
+            "Inhibit",
+            &(
+                self.profile.logind_what(),
+                "agent-lock",
+                "Keeping background tasks running",
+                "block",
+            ),
+        )?;
+        self.inhibitor = Some(reply.body().deserialize()?);
+        Ok(())
+    }
+
     #[cfg(target_os = "macos")]
     pub fn stop(&mut self) {
         if !self.running.load(Ordering::SeqCst) {
@@ -83,6 +209,7 @@ impl SleepPreventer {
         if let Some(handle) = self.handle.take() {
             let _ = handle.join();
         }
+        *self.status.lock().unwrap() = SleepStatus::Stopped;
     }
 
     #[cfg(target_os = "windows")]
@@ -98,6 +225,49 @@ impl SleepPreventer {
         }
 
         self.running.store(false, Ordering::SeqCst);
+        *self.status.lock().unwrap() = SleepStatus::Stopped;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn stop(&mut self) {
+        // Closing the fd releases the inhibitor lock.
+        self.inhibitor = None;
+        self.running.store(false, Ordering::SeqCst);
+        *self.status.lock().unwrap() = SleepStatus::Stopped;
+    }
+
+    /// A preventer that counts as already started but holds nothing, so
+    /// tests of the lock flow leave the machine's power settings alone.
+    #[cfg(test)]
+    pub fn inert(profile: SleepProfile) -> Self {
+        let preventer = Self::new(profile);
+        preventer.running.store(true, Ordering::SeqCst);
+        *preventer.status.lock().unwrap() = SleepStatus::Active;
+        preventer
+    }
+
+    pub fn status(&self) -> SleepStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// The shared status, for watching it from another thread.
+    pub fn status_handle(&self) -> Arc<Mutex<SleepStatus>> {
+        Arc::clone(&self.status)
+    }
+
+    pub fn profile(&self) -> SleepProfile {
+        self.profile
+    }
+}
+
+/// How this platform keeps the machine awake.
+pub fn mechanism() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "caffeinate"
+    } else if cfg!(target_os = "windows") {
+        "SetThreadExecutionState"
+    } else {
+        "logind"
     }
 }
 
@@ -107,13 +277,174 @@ impl Drop for SleepPreventer {
     }
 }
 
+#[cfg(target_os = "macos")]
+fn pid_dir() -> Option<PathBuf> {
+    crate::auth::get_config_path()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+}
+
+/// Runs a helper process for as long as `running` is set, restarting it with
+/// exponential backoff when it exits. The child's pid is kept in
+/// `<program>-<our pid>.pid` in `pid_dir`, one file per agent-lock process,
+/// so the next run can clean up after a crash without touching the child of
+/// a lock or `awake` that is still running.
+#[cfg(any(target_os = "macos", all(test, unix)))]
+struct Supervisor {
+    program: String,
+    args: Vec<String>,
+    pid_dir: Option<PathBuf>,
+    pidfile: Option<PathBuf>,
+    base_delay: Duration,
+    max_delay: Duration,
+    poll: Duration,
+}
+
+#[cfg(any(target_os = "macos", all(test, unix)))]
+impl Supervisor {
+    fn new(program: &str, args: &[&str], pid_dir: Option<PathBuf>) -> Self {
+        let pidfile = pid_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}-{}.pid", program, std::process::id())));
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            pid_dir,
+            pidfile,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            poll: Duration::from_millis(250),
+        }
+    }
+
+    /// Delay before restart number `attempts` (counting from 1).
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(16);
+        (self.base_delay * factor).min(self.max_delay)
+    }
+
+    fn run(&self, running: &AtomicBool, status: &Mutex<SleepStatus>) {
+        if let Some(dir) = &self.pid_dir {
+            kill_leftovers(dir, &self.program);
+        }
+
+        let mut attempts = 0;
+        while running.load(Ordering::SeqCst) {
+            let error = match Command::new(&self.program).args(&self.args).spawn() {
+                Ok(mut child) => {
+                    if let Some(pidfile) = &self.pidfile {
+                        let _ = std::fs::write(pidfile, child.id().to_string());
+                    }
+                    *status.lock().unwrap() = SleepStatus::Active;
+
+                    let started = Instant::now();
+                    let exit = loop {
+                        if !running.load(Ordering::SeqCst) {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            break None;
+                        }
+                        match child.try_wait() {
+                            Ok(None) => thread::sleep(self.poll),
+                            Ok(Some(code)) => break Some(format!("exited ({})", code)),
+                            Err(e) => break Some(e.to_string()),
+                        }
+                    };
+                    if let Some(pidfile) = &self.pidfile {
+                        let _ = std::fs::remove_file(pidfile);
+                    }
+                    let Some(exit) = exit else {
+                        return;
+                    };
+                    // A child that stayed up for a while earns a fresh backoff.
+                    if started.elapsed() >= self.max_delay {
+                        attempts = 0;
+                    }
+                    exit
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    *status.lock().unwrap() =
+                        SleepStatus::Unavailable(format!("{} not found", self.program));
+                    return;
+                }
+                Err(e) => format!("failed to start: {}", e),
+            };
+
+            attempts += 1;
+            eprintln!("Warning: {} {}, restarting", self.program, error);
+            *status.lock().unwrap() = SleepStatus::Retrying { attempts, error };
+
+            let deadline = Instant::now() + self.backoff(attempts);
+            while running.load(Ordering::SeqCst) && Instant::now() < deadline {
+                thread::sleep(self.poll.min(deadline - Instant::now()));
+            }
+        }
+    }
+}
+
+/// Clean up after every earlier agent-lock process that recorded a `program`
+/// child in `dir` and is no longer running.
+#[cfg(any(target_os = "macos", all(test, unix)))]
+fn kill_leftovers(dir: &Path, program: &str) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let prefix = format!("{}-", program);
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let owner = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|rest| rest.strip_suffix(".pid"))
+            .and_then(|owner| owner.parse::<u32>().ok());
+        match owner {
+            Some(owner) if owner != std::process::id() && !crate::process::alive(owner) => {
+                kill_leftover(&entry.path(), program);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Kill the process recorded in `pidfile` if it is still running `program`,
+/// then remove the pidfile. The name check keeps a recycled pid from
+/// taking down an unrelated process.
+#[cfg(any(target_os = "macos", all(test, unix)))]
+fn kill_leftover(pidfile: &Path, program: &str) {
+    let Ok(contents) = std::fs::read_to_string(pidfile) else {
+        return;
+    };
+    let _ = std::fs::remove_file(pidfile);
+    let Ok(pid) = contents.trim().parse::<libc::pid_t>() else {
+        return;
+    };
+    if pid <= 0 || unsafe { libc::kill(pid, 0) } != 0 {
+        return;
+    }
+
+    let command = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    if Path::new(&command).file_name() == Some(program.as_ref()) {
+        eprintln!(
+            "Stopping leftover {} (pid {}) from a previous run",
+            program, pid
+        );
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_sleep_preventer_lifecycle() {
-        let mut preventer = SleepPreventer::new();
+        let mut preventer = SleepPreventer::new(SleepProfile::Display);
         assert!(!preventer.running.load(Ordering::SeqCst));
 
         preventer.start().unwrap();
@@ -121,5 +452,197 @@ mod tests {
 
         preventer.stop();
         assert!(!preventer.running.load(Ordering::SeqCst));
+        assert_eq!(preventer.status(), SleepStatus::Stopped);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_logind_inhibitor_per_profile() {
+        assert_eq!(SleepProfile::System.logind_what(), "sleep");
+        assert_eq!(SleepProfile::Display.logind_what(), "sleep:idle");
+        assert_eq!(SleepProfile::Lid.logind_what(), "sleep:handle-lid-switch");
+        assert_eq!(
+            SleepProfile::DisplayLid.logind_what(),
+            "sleep:idle:handle-lid-switch"
+        );
+    }
+
+    #[cfg(unix)]
+    fn quick_supervisor(program: &str, args: &[&str], pidfile: Option<PathBuf>) -> Supervisor {
+        Supervisor {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(40),
+            poll: Duration::from_millis(5),
+            ..Supervisor::new(program, args, pidfile)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_supervisor_backoff() {
+        let supervisor = Supervisor::new("caffeinate", &[], None);
+        let delays: Vec<u64> = (1..=7).map(|n| supervisor.backoff(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_supervisor_restarts_dead_child() {
+        let running = Arc::new(AtomicBool::new(true));
+        let status = Arc::new(Mutex::new(SleepStatus::Stopped));
+        let supervisor = quick_supervisor("sh", &["-c", "exit 3"], None);
+
+        let handle = {
+            let running = Arc::clone(&running);
+            let status = Arc::clone(&status);
+            thread::spawn(move || supervisor.run(&running, &status))
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let current = status.lock().unwrap().clone();
+            if let SleepStatus::Retrying { attempts, error } = current {
+                if attempts >= 3 {
+                    assert!(error.contains("exited"), "{}", error);
+                    break;
+                }
+            }
+            assert!(Instant::now() < deadline, "child was not restarted");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        running.store(false, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_supervisor_cleans_up_leftover_child() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-pids-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A "sleep" child recorded by a run that crashed, and one belonging
+        // to a process that is still running (the test harness).
+        let mut leftover = Command::new("sleep").arg("30").spawn().unwrap();
+        std::fs::write(
+            dir.join(format!("sleep-{}.pid", u32::MAX - 1)),
+            leftover.id().to_string(),
+        )
+        .unwrap();
+        let mut other = Command::new("sleep").arg("30").spawn().unwrap();
+        let other_pidfile = dir.join(format!("sleep-{}.pid", std::os::unix::process::parent_id()));
+        std::fs::write(&other_pidfile, other.id().to_string()).unwrap();
+
+        let running = AtomicBool::new(true);
+        let status = Mutex::new(SleepStatus::Stopped);
+        let supervisor = quick_supervisor("sleep", &["30"], Some(dir.clone()));
+        let pidfile = supervisor.pidfile.clone().unwrap();
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| supervisor.run(&running, &status));
+
+            let exit = leftover.wait().unwrap();
+            assert!(!exit.success());
+
+            // The replacement child is tracked in our own pidfile until stopped.
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while *status.lock().unwrap() != SleepStatus::Active {
+                assert!(Instant::now() < deadline, "replacement never started");
+                thread::sleep(Duration::from_millis(5));
+            }
+            let pid = std::fs::read_to_string(&pidfile).unwrap();
+            assert_ne!(pid.trim(), leftover.id().to_string());
+
+            running.store(false, Ordering::SeqCst);
+            handle.join().unwrap();
+        });
+        assert!(!pidfile.exists());
+
+        // The live owner's child was left alone.
+        assert!(other.try_wait().unwrap().is_none());
+        assert!(other_pidfile.exists());
+        other.kill().unwrap();
+        other.wait().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Stand-in for logind's `org.freedesktop.login1.Manager`, served over a
+    /// private peer-to-peer connection. It hands out one end of a socket pair
+    /// as the inhibitor fd and keeps the other to see when it is closed.
+    #[cfg(target_os = "linux")]
+    struct FakeLogind {
+        requests: std::sync::mpsc::Sender<(String, String)>,
+        held: std::sync::mpsc::Sender<std::os::unix::net::UnixStream>,
+    }
+
+    #[cfg(target_os = "linux")]
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl FakeLogind {
+        fn inhibit(
+            &self,
+            what: &str,
+            _who: &str,
+            _why: &str,
+            mode: &str,
+        ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd> {
+            let (ours, theirs) = std::os::unix::net::UnixStream::pair()
+                .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+            self.requests
+                .send((what.to_string(), mode.to_string()))
+                .unwrap();
+            self.held.send(ours).unwrap();
+            Ok(std::os::fd::OwnedFd::from(theirs).into())
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_inhibitor_held_until_stop() {
+        use std::io::Read;
+        use std::os::unix::net::UnixStream;
+        use std::sync::mpsc;
+        use std::time::Duration;
+        use zbus::blocking::connection::Builder;
+
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let (requests_tx, requests) = mpsc::channel();
+        let (held_tx, held) = mpsc::channel();
+        let logind = FakeLogind {
+            requests: requests_tx,
+            held: held_tx,
+        };
+
+        let server = thread::spawn(move || {
+            Builder::async_io_unix_stream(server_stream)
+                .p2p()
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .serve_at("/org/freedesktop/login1", logind)
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let client = Builder::async_io_unix_stream(client_stream)
+            .p2p()
+            .build()
+            .unwrap();
+        let _server = server.join().unwrap();
+
+        let mut preventer = SleepPreventer::new(SleepProfile::Lid);
+        preventer.inhibit(&client).unwrap();
+        assert_eq!(
+            requests.recv().unwrap(),
+            ("sleep:handle-lid-switch".to_string(), "block".to_string())
+        );
+
+        let mut ours = held.recv().unwrap();
+        ours.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0u8; 1];
+        // Still held: the read times out instead of seeing EOF.
+        assert!(ours.read(&mut buf).is_err());
+
+        preventer.stop();
+        ours.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(ours.read(&mut buf).unwrap(), 0);
     }
 }
@@ -0,0 +1,224 @@
+use crate::auth::{self, AuthConfig, CredentialKind, LockoutState, TotpMode};
+use crate::control::{self, DaemonStatus, Request};
+use crate::session::{self, SessionKind, SessionRecord};
+use crate::sleep_prevention;
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Everything `agent-lock status --json` reports.
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    pub config_path: PathBuf,
+    pub configured: bool,
+    /// `None` when no PIN is configured or `auth.json` is unreadable.
+    pub auth: Option<AuthReport>,
+    pub lock: LockReport,
+    pub daemon: DaemonReport,
+    pub sleep: SleepReport,
+    pub lockout: LockoutReport,
+    /// Unix timestamp (seconds) of the last successful unlock.
+    pub last_unlock: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuthReport {
+    pub credential: CredentialKind,
+    pub scheme: &'static str,
+    pub hash_version: Option<u32>,
+    pub needs_rehash: bool,
+    pub totp: Option<TotpMode>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LockReport {
+    pub running: bool,
+    pub pid: Option<u32>,
+    pub started: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DaemonReport {
+    pub running: bool,
+    #[serde(flatten)]
+    pub status: Option<DaemonStatus>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SleepReport {
+    pub active: bool,
+    pub mechanism: &'static str,
+    /// What holds the machine awake: `lock`, `run` or `awake`.
+    pub session: Option<SessionKind>,
+    pub profile: Option<String>,
+    pub state: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LockoutReport {
+    pub failed_attempts: u32,
+    pub locked_until: Option<u64>,
+    pub remaining_secs: Option<u64>,
+}
+
+impl StatusReport {
+    /// Gather the report for this user, asking the daemon over its control
+    /// socket if one is running.
+    pub fn collect() -> Result<Self> {
+        let config_path = auth::get_config_path()?;
+        let sessions = session::running(&session::get_sessions_dir()?);
+        let daemon = control::send(Request::Status)
+            .ok()
+            .and_then(|response| response.status);
+        Ok(Self::build(config_path, sessions, daemon, auth::unix_now()))
+    }
+
+    fn build(
+        config_path: PathBuf,
+        sessions: Vec<SessionRecord>,
+        daemon: Option<DaemonStatus>,
+        now: u64,
+    ) -> Self {
+        let configured = config_path.exists();
+        let auth = configured
+            .then(|| AuthConfig::load(&config_path).ok())
+            .flatten()
+            .map(|config| AuthReport {
+                credential: config.credential.kind,
+                scheme: config.scheme().name(),
+                hash_version: config.hash_version(),
+                needs_rehash: config.needs_rehash(),
+                totp: config.totp.map(|totp| totp.mode),
+            });
+        let lockout =
+            LockoutState::load(&config_path.with_file_name("lockout.json")).unwrap_or_default();
+
+        let lock_session = sessions.iter().find(|s| s.kind == SessionKind::Lock);
+        let lock = LockReport {
+            running: lock_session.is_some(),
+            pid: lock_session.map(|s| s.pid),
+            started: lock_session.map(|s| s.started),
+        };
+        // The session keeping the machine awake, or else the newest one, so
+        // its trouble shows.
+        let session = sessions
+            .iter()
+            .find(|s| s.sleep_active())
+            .or(sessions.last())
+            .cloned();
+        let sleep = SleepReport {
+            active: session.as_ref().is_some_and(SessionRecord::sleep_active),
+            mechanism: sleep_prevention::mechanism(),
+            session: session.as_ref().map(|s| s.kind),
+            profile: session.as_ref().map(|s| s.sleep_profile.clone()),
+            state: session.as_ref().map(|s| s.sleep_state.clone()),
+            detail: session.and_then(|s| s.sleep_detail),
+        };
+
+        Self {
+            config_path,
+            configured,
+            auth,
+            lock,
+            daemon: DaemonReport {
+                running: daemon.is_some(),
+                status: daemon,
+            },
+            sleep,
+            lockout: LockoutReport {
+                failed_attempts: lockout.failed_attempts,
+                locked_until: lockout.locked_until,
+                remaining_secs: lockout.remaining(now).map(|d| d.as_secs()),
+            },
+            last_unlock: lockout.last_unlock,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_report_shape() {
+        let dir = std::env::temp_dir().join(format!("agent-lock-status-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("auth.json");
+
+        let report = StatusReport::build(config_path.clone(), Vec::new(), None, 1_000);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["configured"], false);
+        assert!(json["auth"].is_null());
+        assert_eq!(json["lock"]["running"], false);
+        assert_eq!(json["daemon"], serde_json::json!({ "running": false }));
+        assert_eq!(json["sleep"]["active"], false);
+        assert_eq!(json["sleep"]["mechanism"], sleep_prevention::mechanism());
+        assert!(json["last_unlock"].is_null());
+
+        fs::write(&config_path, r#"{"pin_hash": "00"}"#).unwrap();
+        LockoutState {
+            failed_attempts: 4,
+            locked_until: Some(1_030),
+            last_unlock: Some(900),
+        }
+        .save(&dir.join("lockout.json"))
+        .unwrap();
+        let lock = SessionRecord {
+            kind: SessionKind::Lock,
+            pid: 4242,
+            started: 950,
+            sleep_profile: "system+display".to_string(),
+            sleep_state: "active".to_string(),
+            sleep_detail: None,
+        };
+        // A newer `awake` whose caffeinate never started does not hide the
+        // lock.
+        let awake = SessionRecord {
+            kind: SessionKind::Awake,
+            pid: 4300,
+            started: 960,
+            sleep_profile: "system".to_string(),
+            sleep_state: "starting".to_string(),
+            sleep_detail: None,
+        };
+        let daemon = DaemonStatus {
+            pid: 4000,
+            locked: true,
+            lock_pid: Some(4242),
+            hotkey: "super+shift+l".to_string(),
+            idle_lock_secs: None,
+        };
+
+        let report = StatusReport::build(
+            config_path.clone(),
+            vec![lock, awake.clone()],
+            Some(daemon),
+            1_000,
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["auth"]["credential"], "pin");
+        assert_eq!(json["auth"]["scheme"], "sha256-legacy");
+        assert!(json["auth"]["hash_version"].is_null());
+        assert!(json["auth"]["totp"].is_null());
+        assert_eq!(json["lock"]["pid"], 4242);
+        assert_eq!(json["daemon"]["running"], true);
+        assert_eq!(json["daemon"]["lock_pid"], 4242);
+        assert_eq!(json["sleep"]["active"], true);
+        assert_eq!(json["sleep"]["session"], "lock");
+        assert_eq!(json["sleep"]["profile"], "system+display");
+        assert_eq!(json["lockout"]["remaining_secs"], 30);
+        assert_eq!(json["last_unlock"], 900);
+
+        // On its own, a session still starting is not keeping anything awake.
+        let report = StatusReport::build(config_path, vec![awake], None, 1_000);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["lock"]["running"], false);
+        assert_eq!(json["sleep"]["active"], false);
+        assert_eq!(json["sleep"]["session"], "awake");
+        assert_eq!(json["sleep"]["state"], "starting");
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,118 @@
+//! Time-based one-time passwords (RFC 6238) as shown by authenticator apps:
+//! HMAC-SHA1, 6 digits, 30 second steps.
+
+use anyhow::{Context, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use sha1::Sha1;
+
+pub const DIGITS: usize = 6;
+pub const PERIOD_SECS: u64 = 30;
+const SECRET_BYTES: usize = 20;
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// A new random shared secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(BASE32, &bytes)
+}
+
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+    base32::decode(BASE32, &secret.to_ascii_uppercase()).context("TOTP secret is not valid base32")
+}
+
+/// The time step `now` (unix seconds) falls in.
+pub fn step_at(now: u64) -> u64 {
+    now / PERIOD_SECS
+}
+
+/// The code for time step `step` (RFC 4226 HOTP with the step as counter).
+pub fn code_at(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+/// The step whose code is `code`, looking up to `skew` steps either side of
+/// `now` to allow for clock drift.
+pub fn matching_step(secret: &[u8], code: &str, now: u64, skew: u64) -> Option<u64> {
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let current = step_at(now);
+    (current.saturating_sub(skew)..=current + skew).find(|&step| {
+        crate::auth::constant_time_eq(code_at(secret, step).as_bytes(), code.as_bytes())
+    })
+}
+
+/// The `otpauth://` URI authenticator apps import, labelled with `account`.
+pub fn uri(secret: &str, account: &str) -> String {
+    let account: String = account
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .collect();
+    format!(
+        "otpauth://totp/agent-lock:{}?secret={}&issuer=agent-lock&algorithm=SHA1&digits={}&period={}",
+        account, secret, DIGITS, PERIOD_SECS
+    )
+}
+
+/// `uri` as a QR code drawn with Unicode half blocks, light on dark so it
+/// scans from a typical terminal.
+pub fn qr_code(uri: &str) -> Result<String> {
+    let code = QrCode::new(uri.as_bytes()).context("Failed to build QR code")?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // RFC 6238 appendix B, SHA1, truncated to 6 digits.
+        let secret = b"12345678901234567890";
+        assert_eq!(code_at(secret, step_at(59)), "287082");
+        assert_eq!(code_at(secret, step_at(1_111_111_109)), "081804");
+        assert_eq!(code_at(secret, step_at(2_000_000_000)), "279037");
+
+        let encoded = base32::encode(BASE32, secret);
+        assert_eq!(decode_secret(&encoded.to_lowercase()).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_skew_window() {
+        let secret = b"12345678901234567890";
+        let now = 1_111_111_109;
+        let previous = code_at(secret, step_at(now) - 1);
+
+        assert_eq!(
+            matching_step(secret, &previous, now, 1),
+            Some(step_at(now) - 1)
+        );
+        assert_eq!(matching_step(secret, &previous, now, 0), None);
+        assert_eq!(matching_step(secret, "12345", now, 1), None);
+        assert!(uri("ABC", "me@host").starts_with("otpauth://totp/agent-lock:mehost?secret=ABC&"));
+    }
+}